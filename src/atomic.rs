@@ -0,0 +1,135 @@
+use crate::Result;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Replaces the file at `path` with whatever `write` produces without ever leaving it half
+/// written. The contents go to a temporary file in the same directory which is flushed to disk
+/// and then renamed over the original, if anything fails the original is left untouched. The new
+/// file keeps the permissions of the original. When `backup` is set the previous version is kept
+/// next to the file with a `.bak` extension
+pub(crate) fn write_file<F>(path: &Path, backup: bool, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let temp_path = sibling_path(path, |name| {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".tmp.{}", process::id()));
+        temp_name
+    });
+
+    let result = write_temp_file(path, &temp_path, write).and_then(|()| {
+        if backup && path.exists() {
+            backup_file(path)?;
+        }
+
+        fs::rename(&temp_path, path)?;
+        sync_parent(path)
+    });
+
+    if result.is_err() {
+        // the temp file may not exist depending on where things went wrong
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    suffixed_path(path, ".bak")
+}
+
+fn write_temp_file<F>(path: &Path, temp_path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let file = File::create(temp_path)?;
+
+    // so a file only its owner can read isn't replaced by one anyone can read
+    match fs::metadata(path) {
+        Ok(metadata) => file.set_permissions(metadata.permissions())?,
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => return Err(error.into()),
+    }
+
+    let mut writer = BufWriter::new(file);
+
+    write(&mut writer)?;
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    Ok(())
+}
+
+/// Keeps the current version of the file as the backup, replacing any older backup. A hard link
+/// is used where possible so the original stays in place until the rename replaces it
+fn backup_file(path: &Path) -> Result<()> {
+    let backup_path = backup_path(path);
+
+    match fs::remove_file(&backup_path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+        _ => {}
+    }
+
+    if fs::hard_link(path, &backup_path).is_err() {
+        fs::copy(path, &backup_path)?;
+    }
+
+    Ok(())
+}
+
+/// Flushes the directory entry for the rename to disk, only possible on unix like systems
+fn sync_parent(path: &Path) -> Result<()> {
+    if cfg!(unix) {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+fn sibling_path(path: &Path, name: impl FnOnce(&OsStr) -> OsString) -> PathBuf {
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+
+    path.with_file_name(name(file_name))
+}
+
+/// The path of a file kept next to the one at `path`, named after it with `suffix` added
+pub(crate) fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    sibling_path(path, |name| {
+        let mut suffixed_name = name.to_os_string();
+        suffixed_name.push(suffix);
+        suffixed_name
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::ScratchDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn replacement_keeps_the_original_permissions() {
+        let dir = ScratchDir::new("atomic");
+        let path = dir.join("contacts.json");
+
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_file(&path, false, |writer| Ok(writer.write_all(b"new")?)).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,121 @@
+//! Ordering text the way people expect to see names sorted rather than by code point, where
+//! "Zoë" would come after "zulu" and "Émile" after every unaccented name.
+//!
+//! Strings are compared in levels like the Unicode Collation Algorithm: first by their letters
+//! and digits ignoring case, accents, spaces and punctuation, then by accents, then by case with
+//! lower case first.
+//!
+//! The ordering is the same whatever the user's locale, there is no tailoring for languages that
+//! sort some letters apart from their base letter, so Swedish "Åsa" sorts with "Asa" rather than
+//! after "Zoë" and Spanish "ñ" sorts as "n"
+
+use caseless::Caseless;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// A string's sort key, comparing keys compares the strings level by level. Strings that only
+/// differ in ways no level sees are ordered by code point so distinct strings never compare equal.
+/// Building a key folds the string, so when sorting many strings build each key once
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Key {
+    primary: String,
+    secondary: String,
+    tertiary: Vec<bool>,
+    text: String,
+}
+
+pub(crate) fn key(s: &str) -> Key {
+    Key {
+        primary: primary_key(s),
+        secondary: secondary_key(s),
+        tertiary: tertiary_key(s),
+        text: s.to_string(),
+    }
+}
+
+/// Takes the compatibility decomposition of the string (NFKD) and drops the accents it splits
+/// off, then folds case, so "Ştefan" folds to "stefan", "Nguyễn" to "nguyen" and "ﬁnn" to "finn".
+/// Letters that have no decomposition but are written with a stroke or as a ligature, such as
+/// "ø", "ł" and "æ", are replaced with the letters they are based on as well
+pub(crate) fn fold(s: &str) -> String {
+    // folding case can leave characters that decompose further, "ᾈ" folds to "ἀι"
+    let decomposed = s.nfkd().default_case_fold().nfkd();
+    let mut folded = String::with_capacity(s.len());
+
+    for c in decomposed {
+        match base_letters(c) {
+            Some(base) => folded.push_str(base),
+            None if is_combining_mark(c) => {}
+            None => folded.push(c),
+        }
+    }
+
+    folded
+}
+
+/// Letters and digits only, ignoring case and accents
+fn primary_key(s: &str) -> String {
+    fold(s).chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+/// Letters and digits with their accents, ignoring case
+fn secondary_key(s: &str) -> String {
+    s.chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+/// Whether each letter is upper case, lower case sorts first
+fn tertiary_key(s: &str) -> Vec<bool> {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .map(char::is_uppercase)
+        .collect()
+}
+
+/// The letters a lower case letter without a decomposition is based on
+fn base_letters(c: char) -> Option<&'static str> {
+    let base = match c {
+        'đ' | 'ð' => "d",
+        'ħ' => "h",
+        'ı' => "i",
+        'ł' => "l",
+        'ø' => "o",
+        'ŧ' => "t",
+        'æ' => "ae",
+        'œ' => "oe",
+        'þ' => "th",
+        _ => return None,
+    };
+
+    Some(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_drops_accents_and_compatibility_forms() {
+        assert_eq!(fold("Ștefan"), "stefan");
+        assert_eq!(fold("Nguyễn"), "nguyen");
+        assert_eq!(fold("ﬁnn"), "finn");
+        assert_eq!(fold("Ｚoë\u{a0}"), "zoe ");
+        assert_eq!(fold("Łukasz Øster"), "lukasz oster");
+    }
+
+    #[test]
+    fn fold_goes_beyond_lower_case() {
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(fold("ΣΊΣΥΦΟΣ"), fold("σίσυφος"));
+        assert_eq!(fold("ſ"), "s");
+    }
+
+    #[test]
+    fn keys_order_by_letters_then_accents_then_case() {
+        let mut names = ["zulu", "Émile", "Zoë", "emile", "Emile", "Zoe"];
+        names.sort_by_key(|name| key(name));
+        assert_eq!(names, ["emile", "Emile", "Émile", "Zoe", "Zoë", "zulu"]);
+    }
+}
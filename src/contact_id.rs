@@ -0,0 +1,185 @@
+use crate::{PhoneBookError, PhoneNumber};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Crockford's base 32 alphabet as used by ULIDs
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ENCODED_LENGTH: usize = 26;
+
+/// A stable identifier for a contact that does not change when its phone numbers do. Identifiers
+/// follow the ULID layout, a 48 bit millisecond timestamp followed by 80 random bits, written as
+/// 26 characters of Crockford's base 32
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ContactId(u128);
+
+impl ContactId {
+    /// A new id from the current time and random bits
+    pub fn generate() -> ContactId {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+
+        ContactId((millis & ((1 << 48) - 1)) << 80 | random_bits() & ((1 << 80) - 1))
+    }
+
+    /// The id of a contact read from a file written before contacts had ids, derived from its
+    /// phone number so the contact has the same id every time the file is loaded. E.164 numbers
+    /// have at most 15 digits so the number fits in the random bits with the timestamp left at 0
+    pub fn from_legacy_number(number: &PhoneNumber) -> ContactId {
+        let value = number
+            .digits()
+            .bytes()
+            .fold(0, |value, digit| value * 10 + u128::from(digit - b'0'));
+
+        ContactId(value)
+    }
+}
+
+/// 128 unpredictable bits without pulling in a random number crate, `RandomState` is seeded from
+/// the operating system and hashing a process wide counter with it avoids repeats
+pub(crate) fn random_bits() -> u128 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let state = RandomState::new();
+    let mut bits = 0;
+
+    for _ in 0..2 {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        bits = bits << 64 | u128::from(hasher.finish());
+    }
+
+    bits
+}
+
+impl FromStr for ContactId {
+    type Err = PhoneBookError;
+
+    fn from_str(s: &str) -> Result<ContactId, PhoneBookError> {
+        let invalid = || PhoneBookError::InvalidContactId(s.to_string());
+
+        if s.len() != ENCODED_LENGTH {
+            return Err(invalid());
+        }
+
+        let mut value: u128 = 0;
+        for (index, c) in s.bytes().enumerate() {
+            let digit = ALPHABET
+                .iter()
+                .position(|a| *a == c.to_ascii_uppercase())
+                .ok_or_else(invalid)?;
+
+            // 26 characters hold 130 bits so the first character can only use its low 3 bits
+            if index == 0 && digit > 7 {
+                return Err(invalid());
+            }
+
+            value = value << 5 | digit as u128;
+        }
+
+        Ok(ContactId(value))
+    }
+}
+
+impl TryFrom<String> for ContactId {
+    type Error = PhoneBookError;
+
+    fn try_from(s: String) -> Result<ContactId, PhoneBookError> {
+        s.parse()
+    }
+}
+
+impl From<ContactId> for String {
+    fn from(id: ContactId) -> String {
+        id.to_string()
+    }
+}
+
+impl Display for ContactId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let encoded = (0..ENCODED_LENGTH)
+            .map(|index| {
+                let shift = 5 * (ENCODED_LENGTH - 1 - index);
+                ALPHABET[(self.0 >> shift) as usize & 31] as char
+            })
+            .collect::<String>();
+
+        f.write_str(&encoded)
+    }
+}
+
+impl Debug for ContactId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ContactId({:?})", self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_round_trip_through_their_text_form() {
+        for id in [ContactId::generate(), ContactId(0), ContactId(u128::MAX)] {
+            let text = id.to_string();
+            assert_eq!(text.len(), ENCODED_LENGTH);
+            assert_eq!(text.parse::<ContactId>().unwrap(), id);
+        }
+
+        assert_eq!(
+            ContactId(u128::MAX).to_string(),
+            "7ZZZZZZZZZZZZZZZZZZZZZZZZZ"
+        );
+        assert_eq!(
+            "01arz3ndektsv4rrffq69g5fav".parse::<ContactId>().unwrap(),
+            "01ARZ3NDEKTSV4RRFFQ69G5FAV".parse::<ContactId>().unwrap()
+        );
+    }
+
+    #[test]
+    fn ids_that_dont_fit_in_128_bits_are_rejected() {
+        assert!("7ZZZZZZZZZZZZZZZZZZZZZZZZZ".parse::<ContactId>().is_ok());
+        assert!(matches!(
+            "8ZZZZZZZZZZZZZZZZZZZZZZZZZ".parse::<ContactId>(),
+            Err(PhoneBookError::InvalidContactId(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        for id in [
+            "",
+            "01ARZ3NDEKTSV4RRFFQ69G5FA",
+            "01ARZ3NDEKTSV4RRFFQ69G5FAVV",
+            "01ARZ3NDEKTSV4RRFFQ69G5FAU",
+        ] {
+            assert!(id.parse::<ContactId>().is_err(), "{:?} was accepted", id);
+        }
+    }
+
+    #[test]
+    fn legacy_numbers_always_give_the_same_id() {
+        let number = |s: &str| s.parse::<PhoneNumber>().unwrap();
+
+        let id = ContactId::from_legacy_number(&number("5551234567"));
+        assert_eq!(id, ContactId::from_legacy_number(&number("555-123-4567")));
+        assert_ne!(id, ContactId::from_legacy_number(&number("5551234568")));
+        assert_eq!(id, ContactId(15551234567));
+    }
+
+    #[test]
+    fn generated_ids_are_unique() {
+        let a = ContactId::generate();
+        let b = ContactId::generate();
+
+        assert_ne!(a, b);
+    }
+}
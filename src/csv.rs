@@ -0,0 +1,678 @@
+//! Reading and writing contacts as CSV ([RFC 4180]), the format spreadsheets and most address
+//! books can export. Which column holds which part of a contact is set by a [`ColumnMapping`],
+//! with presets for the layouts written by this tool, Google Contacts and Outlook.
+//!
+//! [RFC 4180]: https://www.rfc-editor.org/rfc/rfc4180
+
+use crate::{
+    Address, Contact, LabelledNumber, PhoneBookError, PhoneLabel, PhoneNumber, Region, Result,
+};
+use std::io::Write;
+
+/// Google Contacts puts every number with the same label in one cell separated by this
+const VALUE_SEPARATOR: &str = ":::";
+
+/// Marks the label of the primary number in a phone type column
+const PRIMARY_MARKER: char = '*';
+
+/// The part of a contact held in a column
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    FirstName,
+    LastName,
+    /// The contact's primary number, on import it is merged with the same number in any other
+    /// column
+    PrimaryPhone,
+    /// A number with a fixed label, only the first number with the label is exported
+    Phone(PhoneLabel),
+    /// The nth number of the contact counting from 1, the primary number is always first when
+    /// exporting
+    PhoneValue(usize),
+    /// The label of the number in the [`Column::PhoneValue`] column with the same position, the
+    /// primary number's label starts with `*`
+    PhoneType(usize),
+    StreetAddress,
+    City,
+    State,
+    Postcode,
+    Country,
+}
+
+/// The header and meaning of each column in a CSV file. A file whose first row holds any of the
+/// headers is read by header, otherwise its columns are read in the same order as the mapping
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub columns: Vec<(String, Column)>,
+}
+
+impl ColumnMapping {
+    /// The layout written by default, with room for three phone numbers
+    pub fn native() -> ColumnMapping {
+        ColumnMapping::native_with_phones(3)
+    }
+
+    /// The default layout with room for `phones` phone numbers
+    pub fn native_with_phones(phones: usize) -> ColumnMapping {
+        let mut columns = vec![
+            ("first_name".to_string(), Column::FirstName),
+            ("last_name".to_string(), Column::LastName),
+        ];
+        for position in 1..=phones {
+            columns.push((
+                format!("phone_{}_label", position),
+                Column::PhoneType(position),
+            ));
+            columns.push((format!("phone_{}", position), Column::PhoneValue(position)));
+        }
+        columns.extend([
+            ("street_address".to_string(), Column::StreetAddress),
+            ("city".to_string(), Column::City),
+            ("state".to_string(), Column::State),
+            ("postcode".to_string(), Column::Postcode),
+            ("country".to_string(), Column::Country),
+        ]);
+
+        ColumnMapping { columns }
+    }
+
+    /// The layout of Google Contacts exports, with room for five phone numbers
+    pub fn google() -> ColumnMapping {
+        let mut columns = vec![
+            ("Given Name".to_string(), Column::FirstName),
+            ("Family Name".to_string(), Column::LastName),
+        ];
+        for position in 1..=5 {
+            columns.push((
+                format!("Phone {} - Type", position),
+                Column::PhoneType(position),
+            ));
+            columns.push((
+                format!("Phone {} - Value", position),
+                Column::PhoneValue(position),
+            ));
+        }
+        columns.extend([
+            ("Address 1 - Street".to_string(), Column::StreetAddress),
+            ("Address 1 - City".to_string(), Column::City),
+            ("Address 1 - Region".to_string(), Column::State),
+            ("Address 1 - Postal Code".to_string(), Column::Postcode),
+            ("Address 1 - Country".to_string(), Column::Country),
+        ]);
+
+        ColumnMapping { columns }
+    }
+
+    /// The layout of Outlook exports, which has a column per label so custom labels other than
+    /// "other" can't be exported
+    pub fn outlook() -> ColumnMapping {
+        ColumnMapping::from_pairs(vec![
+            ("First Name", Column::FirstName),
+            ("Last Name", Column::LastName),
+            ("Primary Phone", Column::PrimaryPhone),
+            ("Mobile Phone", Column::Phone(PhoneLabel::Mobile)),
+            ("Home Phone", Column::Phone(PhoneLabel::Home)),
+            ("Business Phone", Column::Phone(PhoneLabel::Work)),
+            ("Business Fax", Column::Phone(PhoneLabel::Fax)),
+            (
+                "Other Phone",
+                Column::Phone(PhoneLabel::Custom("other".to_string())),
+            ),
+            ("Home Street", Column::StreetAddress),
+            ("Home City", Column::City),
+            ("Home State", Column::State),
+            ("Home Postal Code", Column::Postcode),
+            ("Home Country/Region", Column::Country),
+        ])
+    }
+
+    fn from_pairs(columns: Vec<(&str, Column)>) -> ColumnMapping {
+        ColumnMapping {
+            columns: columns
+                .into_iter()
+                .map(|(header, column)| (header.to_string(), column))
+                .collect(),
+        }
+    }
+
+    /// Position in the file of each column of the mapping, `None` for columns the file doesn't
+    /// have. Returns whether the first record is a header
+    fn locate(&self, first: &[String]) -> (bool, Vec<Option<usize>>) {
+        let position = |header: &str| {
+            first
+                .iter()
+                .position(|cell| cell.trim().eq_ignore_ascii_case(header))
+        };
+
+        if self
+            .columns
+            .iter()
+            .any(|(header, _)| position(header).is_some())
+        {
+            let positions = self
+                .columns
+                .iter()
+                .map(|(header, _)| position(header))
+                .collect();
+            (true, positions)
+        } else {
+            (false, (0..self.columns.len()).map(Some).collect())
+        }
+    }
+}
+
+/// A data row read from a CSV file
+#[derive(Debug)]
+pub struct Row {
+    /// Line the row starts on
+    pub line: usize,
+    /// The first and last name in the row
+    pub name: String,
+    /// The contact in the row or why it couldn't be read, the contact has not been validated
+    pub contact: Result<Contact>,
+}
+
+/// Reads every row of `input` with a contact in it, empty rows are ignored. A row that can't be
+/// turned into a contact is returned with the reason rather than failing the whole file, only
+/// text that isn't valid CSV is an error. Numbers without a country calling code are read as
+/// national numbers of `region`
+pub fn read(input: &str, mapping: &ColumnMapping, region: &'static Region) -> Result<Vec<Row>> {
+    let records = parse_records(input)?;
+    let (has_header, positions) = match records.first() {
+        Some((_, first)) => mapping.locate(first),
+        None => return Ok(Vec::new()),
+    };
+
+    let rows = records
+        .iter()
+        .skip(usize::from(has_header))
+        .filter(|(_, record)| record.iter().any(|cell| !cell.trim().is_empty()))
+        .map(|(line, record)| {
+            let cells = positions
+                .iter()
+                .map(|position| {
+                    position
+                        .and_then(|position| record.get(position))
+                        .map(|cell| cell.trim())
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>();
+
+            to_row(*line, mapping, &cells, region)
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+/// Writes a header row and a row for each contact
+pub fn write<'a, W: Write>(
+    mut writer: W,
+    contacts: impl IntoIterator<Item = &'a Contact>,
+    mapping: &ColumnMapping,
+) -> Result<()> {
+    let header = mapping
+        .columns
+        .iter()
+        .map(|(header, _)| header.clone())
+        .collect::<Vec<_>>();
+    write_record(&mut writer, &header)?;
+
+    for contact in contacts {
+        let record = mapping
+            .columns
+            .iter()
+            .map(|(_, column)| cell(contact, column))
+            .collect::<Vec<_>>();
+        write_record(&mut writer, &record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn to_row(line: usize, mapping: &ColumnMapping, cells: &[&str], region: &'static Region) -> Row {
+    let value = |wanted: &Column| {
+        mapping
+            .columns
+            .iter()
+            .zip(cells)
+            .find(|((_, column), _)| column == wanted)
+            .map(|(_, cell)| cell.to_string())
+            .unwrap_or_default()
+    };
+
+    let first_name = value(&Column::FirstName);
+    let last_name = value(&Column::LastName);
+    let name = format!("{} {}", first_name, last_name).trim().to_string();
+
+    let contact = if name.is_empty() {
+        Err(invalid(line, "row has no name"))
+    } else {
+        to_contact(mapping, cells, region).map(|(phone_numbers, address)| {
+            Contact::new(first_name, last_name, phone_numbers, address)
+        })
+    };
+
+    Row {
+        line,
+        name,
+        contact,
+    }
+}
+
+fn to_contact(
+    mapping: &ColumnMapping,
+    cells: &[&str],
+    region: &'static Region,
+) -> Result<(Vec<LabelledNumber>, Option<Address>)> {
+    let columns = || mapping.columns.iter().map(|(_, column)| column).zip(cells);
+
+    let mut phone_numbers: Vec<LabelledNumber> = Vec::new();
+    let mut primary = None;
+    let mut address = Address {
+        street_address: String::new(),
+        city: String::new(),
+        state: String::new(),
+        postcode: String::new(),
+        country: String::new(),
+    };
+
+    for (column, cell) in columns() {
+        let (label, is_primary) = match column {
+            Column::Phone(label) => (label.clone(), false),
+            Column::PhoneValue(position) => {
+                let label = columns()
+                    .find(|(column, _)| **column == Column::PhoneType(*position))
+                    .map(|(_, cell)| *cell)
+                    .unwrap_or_default();
+
+                match label.strip_prefix(PRIMARY_MARKER) {
+                    Some(label) => (parse_label(label), true),
+                    None => (parse_label(label), false),
+                }
+            }
+            Column::StreetAddress => {
+                address.street_address = cell.to_string();
+                continue;
+            }
+            Column::City => {
+                address.city = cell.to_string();
+                continue;
+            }
+            Column::State => {
+                address.state = cell.to_string();
+                continue;
+            }
+            Column::Postcode => {
+                address.postcode = cell.to_string();
+                continue;
+            }
+            Column::Country => {
+                address.country = cell.to_string();
+                continue;
+            }
+            Column::FirstName | Column::LastName | Column::PrimaryPhone | Column::PhoneType(_) => {
+                continue
+            }
+        };
+
+        for number in split_numbers(cell) {
+            let number = PhoneNumber::parse(number, region)?;
+
+            // a number repeated under another label keeps the first label it was found with
+            let index = match phone_numbers.iter().position(|n| n.number == number) {
+                Some(index) => index,
+                None => {
+                    phone_numbers.push(LabelledNumber {
+                        label: label.clone(),
+                        number,
+                        primary: false,
+                    });
+                    phone_numbers.len() - 1
+                }
+            };
+
+            if is_primary {
+                primary = primary.or(Some(index));
+            }
+        }
+    }
+
+    // the primary column only says which number is primary, it is usually repeated in a
+    // labelled column which gives the number its label
+    for (_, cell) in columns().filter(|(column, _)| **column == Column::PrimaryPhone) {
+        for number in split_numbers(cell) {
+            let number = PhoneNumber::parse(number, region)?;
+
+            let index = match phone_numbers.iter().position(|n| n.number == number) {
+                Some(index) => index,
+                None => {
+                    phone_numbers.push(LabelledNumber {
+                        label: PhoneLabel::Mobile,
+                        number,
+                        primary: false,
+                    });
+                    phone_numbers.len() - 1
+                }
+            };
+            primary = primary.or(Some(index));
+        }
+    }
+
+    if let Some(number) = phone_numbers.get_mut(primary.unwrap_or_default()) {
+        number.primary = true;
+    }
+
+    let has_address = address.fields().iter().any(|(_, value)| !value.is_empty());
+
+    Ok((phone_numbers, has_address.then_some(address)))
+}
+
+/// The numbers in a cell, Google Contacts can put several in one cell
+fn split_numbers(cell: &str) -> impl Iterator<Item = &str> {
+    cell.split(VALUE_SEPARATOR)
+        .map(str::trim)
+        .filter(|number| !number.is_empty())
+}
+
+fn parse_label(label: &str) -> PhoneLabel {
+    let label = label.trim().to_lowercase();
+
+    if label.contains("fax") {
+        PhoneLabel::Fax
+    } else if label.is_empty() || label.contains("mobile") || label.contains("cell") {
+        PhoneLabel::Mobile
+    } else if label.contains("work") || label.contains("business") {
+        PhoneLabel::Work
+    } else if label.contains("home") {
+        PhoneLabel::Home
+    } else {
+        PhoneLabel::Custom(label)
+    }
+}
+
+pub(crate) fn cell(contact: &Contact, column: &Column) -> String {
+    let address = |field: fn(&Address) -> &String| {
+        contact
+            .address
+            .as_ref()
+            .map(|address| field(address).clone())
+            .unwrap_or_default()
+    };
+    // the primary number first so it is in the first phone column
+    let numbered = || {
+        contact
+            .phone_numbers
+            .iter()
+            .filter(|number| number.primary)
+            .chain(
+                contact
+                    .phone_numbers
+                    .iter()
+                    .filter(|number| !number.primary),
+            )
+    };
+
+    match column {
+        Column::FirstName => contact.first_name.clone(),
+        Column::LastName => contact.last_name.clone(),
+        Column::PrimaryPhone => contact
+            .primary_number()
+            .map(PhoneNumber::to_string)
+            .unwrap_or_default(),
+        Column::Phone(label) => contact
+            .phone_numbers
+            .iter()
+            .find(|number| number.label == *label)
+            .map(|number| number.number.to_string())
+            .unwrap_or_default(),
+        Column::PhoneValue(position) => numbered()
+            .nth(position.saturating_sub(1))
+            .map(|number| number.number.to_string())
+            .unwrap_or_default(),
+        Column::PhoneType(position) => numbered()
+            .nth(position.saturating_sub(1))
+            .map(|number| match number.primary {
+                true => format!("{} {}", PRIMARY_MARKER, number.label),
+                false => number.label.to_string(),
+            })
+            .unwrap_or_default(),
+        Column::StreetAddress => address(|address| &address.street_address),
+        Column::City => address(|address| &address.city),
+        Column::State => address(|address| &address.state),
+        Column::Postcode => address(|address| &address.postcode),
+        Column::Country => address(|address| &address.country),
+    }
+}
+
+/// Splits `input` into records of fields paired with the line each record starts on. Quoted
+/// fields can hold separators, line breaks and quotes written twice
+fn parse_records(input: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    // spreadsheets often start UTF-8 files with a byte order mark
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+
+    if quoted {
+        return Err(invalid(record_line, "quoted field is never closed"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+
+    Ok(records)
+}
+
+pub(crate) fn write_record<W: Write>(writer: &mut W, record: &[String]) -> Result<()> {
+    let line = record
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\r', '\n']) || field.trim() != field {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    writer.write_all(line.as_bytes())?;
+    writer.write_all(b"\r\n")?;
+
+    Ok(())
+}
+
+fn invalid(line: usize, reason: impl Into<String>) -> PhoneBookError {
+    PhoneBookError::InvalidCsv {
+        line,
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::labelled;
+
+    fn records(input: &str) -> Vec<(usize, Vec<String>)> {
+        parse_records(input).unwrap()
+    }
+
+    fn record(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|field| field.to_string()).collect()
+    }
+
+    fn names(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(|row| row.name.as_str()).collect()
+    }
+
+    fn read_native(input: &str) -> Vec<Row> {
+        read(input, &ColumnMapping::native(), Region::default_region()).unwrap()
+    }
+
+    #[test]
+    fn quoted_fields_hold_separators_and_doubled_quotes() {
+        assert_eq!(
+            records("a,\"b,c\",\"say \"\"hi\"\"\",\"\"\r\n"),
+            [(1, record(&["a", "b,c", "say \"hi\"", ""]))]
+        );
+    }
+
+    #[test]
+    fn quoted_fields_hold_line_breaks_and_later_records_keep_their_line() {
+        assert_eq!(
+            records("\"Unit 4\r\n12 Long St\",x\r\ny,\"a\nb\nc\"\nz\n"),
+            [
+                (1, record(&["Unit 4\r\n12 Long St", "x"])),
+                (3, record(&["y", "a\nb\nc"])),
+                (6, record(&["z"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn records_end_with_any_line_break() {
+        assert_eq!(
+            records("\u{feff}a,b\r\nc,d\re,f\ng,h"),
+            [
+                (1, record(&["a", "b"])),
+                (2, record(&["c", "d"])),
+                (3, record(&["e", "f"])),
+                (4, record(&["g", "h"])),
+            ]
+        );
+        assert_eq!(records("a,\n"), [(1, record(&["a", ""]))]);
+    }
+
+    #[test]
+    fn unclosed_quotes_are_reported_where_the_record_starts() {
+        assert!(matches!(
+            parse_records("a,b\nc,\"d\ne\n"),
+            Err(PhoneBookError::InvalidCsv { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn written_records_are_quoted_only_when_needed() {
+        let fields = record(&["plain", "a,b", "say \"hi\"", "two\nlines", " padded", ""]);
+        let mut output = Vec::new();
+        write_record(&mut output, &fields).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(
+            output,
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\" padded\",\r\n"
+        );
+        assert_eq!(records(&output), [(1, fields)]);
+    }
+
+    #[test]
+    fn files_with_a_header_are_read_by_header() {
+        let rows = read_native("City,LAST_NAME,First_Name\r\nPerth,Smith,John\r\n");
+        assert_eq!(names(&rows), ["John Smith"]);
+
+        let contact = rows[0].contact.as_ref().unwrap();
+        assert_eq!(contact.address.as_ref().unwrap().city, "Perth");
+        assert!(contact.phone_numbers.is_empty());
+    }
+
+    #[test]
+    fn files_without_a_header_are_read_in_mapping_order() {
+        let rows = read_native("John,Smith,* work,2025550101\r\n,,,\r\nJane,Doe\r\n");
+
+        assert_eq!(names(&rows), ["John Smith", "Jane Doe"]);
+        assert_eq!(rows[1].line, 3);
+
+        let number = &rows[0].contact.as_ref().unwrap().phone_numbers[0];
+        assert_eq!(number.label, PhoneLabel::Work);
+        assert!(number.primary);
+    }
+
+    #[test]
+    fn rows_that_cant_be_read_keep_their_line() {
+        let rows = read_native("first_name,last_name,phone_1\n,,\n,,2025550101\nJo,Li,12\n");
+
+        assert!(matches!(
+            rows[0].contact,
+            Err(PhoneBookError::InvalidCsv { line: 3, .. })
+        ));
+        assert!(matches!(
+            rows[1].contact,
+            Err(PhoneBookError::InvalidPhoneNumber { .. })
+        ));
+    }
+
+    #[test]
+    fn contacts_round_trip() {
+        let mapping = ColumnMapping::google();
+        let contact = Contact::new(
+            "Mary, \"MJ\"".to_string(),
+            "O'Brien".to_string(),
+            vec![
+                labelled("2025550101", PhoneLabel::Home, false),
+                labelled("+61 412 345 678", PhoneLabel::Mobile, true),
+            ],
+            Some(Address {
+                street_address: "Unit 4\n12 Long Street".to_string(),
+                city: "Perth".to_string(),
+                state: "WA".to_string(),
+                postcode: "6000".to_string(),
+                country: String::new(),
+            }),
+        );
+
+        let mut output = Vec::new();
+        write(&mut output, [&contact], &mapping).unwrap();
+        let rows = read(
+            &String::from_utf8(output).unwrap(),
+            &mapping,
+            Region::default_region(),
+        )
+        .unwrap();
+        let read = rows[0].contact.as_ref().unwrap();
+
+        assert_eq!(read.first_name, contact.first_name);
+        assert_eq!(read.last_name, contact.last_name);
+        // the primary number is written first
+        assert_eq!(
+            read.phone_numbers,
+            [
+                contact.phone_numbers[1].clone(),
+                contact.phone_numbers[0].clone()
+            ]
+        );
+        assert_eq!(read.address, contact.address);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{ContactId, PhoneNumber};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{error, fmt, io};
+
+pub type Result<T> = std::result::Result<T, PhoneBookError>;
+
+#[derive(Debug)]
+pub enum PhoneBookError {
+    /// The phone number could not be used, `reason` explains which rule it broke
+    InvalidPhoneNumber {
+        number: String,
+        reason: &'static str,
+    },
+    /// The phone number prefix could not be used, `reason` explains which rule it broke
+    InvalidPrefix {
+        prefix: String,
+        reason: &'static str,
+    },
+    /// No numbering plan metadata is available for this region code
+    UnknownRegion(String),
+    /// The contact breaks one of the rules for its phone numbers, `reason` explains which
+    InvalidContact {
+        reason: &'static str,
+    },
+    /// The address could not be parsed, `reason` explains why
+    InvalidAddress {
+        address: String,
+        reason: &'static str,
+    },
+    /// Another contact already has this phone number
+    DuplicatePhoneNumber(PhoneNumber),
+    /// No contact has this phone number
+    ContactNotFound(PhoneNumber),
+    /// The contact being changed doesn't have this phone number
+    NumberNotOnContact(PhoneNumber),
+    /// The contact id is not a valid ULID
+    InvalidContactId(String),
+    /// Another contact already has this id
+    DuplicateContactId(ContactId),
+    /// No contact has this id
+    ContactIdNotFound(ContactId),
+    Io(io::Error),
+    /// Another process held the lock on the phone book for longer than the timeout
+    LockTimeout {
+        path: PathBuf,
+        timeout: Duration,
+    },
+    /// The storage backend is unknown or was not compiled in
+    UnsupportedStorage(String),
+    /// The storage backend reported an error
+    Storage(String),
+    /// A vCard file could not be read, `line` is where the problem was found
+    InvalidVCard {
+        line: usize,
+        reason: String,
+    },
+    /// A CSV file could not be read, `line` is where the problem was found
+    InvalidCsv {
+        line: usize,
+        reason: String,
+    },
+    /// A name search can't be made, `reason` explains why
+    InvalidNameQuery {
+        reason: &'static str,
+    },
+    /// The keypad digits could not be searched for, `reason` explains why
+    InvalidKeypadDigits {
+        digits: String,
+        reason: &'static str,
+    },
+    /// A search query could not be parsed, `column` counts characters from 1
+    InvalidQuery {
+        column: usize,
+        reason: String,
+    },
+    /// The phone book file is not valid json or does not hold a valid phone book
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl Display for PhoneBookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PhoneBookError::InvalidPhoneNumber { number, reason } => {
+                write!(f, "invalid phone number {:?}: {}", number, reason)
+            }
+            PhoneBookError::InvalidPrefix { prefix, reason } => {
+                write!(f, "invalid phone number prefix {:?}: {}", prefix, reason)
+            }
+            PhoneBookError::UnknownRegion(code) => write!(f, "unknown region {:?}", code),
+            PhoneBookError::InvalidContact { reason } => write!(f, "invalid contact: {}", reason),
+            PhoneBookError::InvalidAddress { address, reason } => {
+                write!(f, "invalid address {:?}: {}", address, reason)
+            }
+            PhoneBookError::DuplicatePhoneNumber(number) => {
+                write!(f, "a contact with phone number {} already exists", number)
+            }
+            PhoneBookError::ContactNotFound(number) => {
+                write!(f, "no contact found with phone number {}", number)
+            }
+            PhoneBookError::NumberNotOnContact(number) => {
+                write!(f, "the contact doesn't have phone number {}", number)
+            }
+            PhoneBookError::InvalidContactId(id) => write!(f, "invalid contact id {:?}", id),
+            PhoneBookError::DuplicateContactId(id) => {
+                write!(f, "a contact with id {} already exists", id)
+            }
+            PhoneBookError::ContactIdNotFound(id) => write!(f, "no contact found with id {}", id),
+            PhoneBookError::Io(error) => write!(f, "{}", error),
+            PhoneBookError::LockTimeout { path, timeout } => write!(
+                f,
+                "unable to lock {} within {:?}, another process is using the phone book",
+                path.display(),
+                timeout
+            ),
+            PhoneBookError::UnsupportedStorage(message) => write!(f, "{}", message),
+            PhoneBookError::Storage(message) => write!(f, "storage error: {}", message),
+            PhoneBookError::InvalidVCard { line, reason } => {
+                write!(f, "invalid vCard at line {}: {}", line, reason)
+            }
+            PhoneBookError::InvalidCsv { line, reason } => {
+                write!(f, "invalid CSV at line {}: {}", line, reason)
+            }
+            PhoneBookError::InvalidNameQuery { reason } => {
+                write!(f, "invalid name search: {}", reason)
+            }
+            PhoneBookError::InvalidKeypadDigits { digits, reason } => {
+                write!(f, "invalid keypad digits {:?}: {}", digits, reason)
+            }
+            PhoneBookError::InvalidQuery { column, reason } => {
+                write!(f, "invalid query at column {}: {}", column, reason)
+            }
+            PhoneBookError::Parse {
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "unable to parse phone book at line {} column {}: {}",
+                line, column, message
+            ),
+        }
+    }
+}
+
+impl error::Error for PhoneBookError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PhoneBookError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PhoneBookError {
+    fn from(error: io::Error) -> PhoneBookError {
+        PhoneBookError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for PhoneBookError {
+    fn from(error: serde_json::Error) -> PhoneBookError {
+        if error.is_io() {
+            return PhoneBookError::Io(error.into());
+        }
+
+        // serde_json appends the position to its message, strip it as it is stored separately
+        let message = error.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) => message[..index].to_string(),
+            None => message,
+        };
+
+        PhoneBookError::Parse {
+            line: error.line(),
+            column: error.column(),
+            message,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for PhoneBookError {
+    fn from(error: rusqlite::Error) -> PhoneBookError {
+        PhoneBookError::Storage(error.to_string())
+    }
+}
@@ -0,0 +1,285 @@
+//! Writing contacts for people and scripts to read, shared by every front end so the formats stay
+//! the same wherever they are shown
+
+use crate::csv::ColumnMapping;
+use crate::{csv, Contact, ContactField, FieldMatch, RankedMatch, Result};
+use serde::Serialize;
+use std::io::Write;
+
+/// Separates the columns of a table
+const COLUMN_GAP: &str = "  ";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Columns aligned for reading in a terminal
+    Table,
+    /// A single json array
+    Json,
+    /// A json object per line
+    JsonLines,
+    /// A header row then a row per contact in the native CSV layout
+    Csv,
+    /// A line per contact in the form of [`Contact`]'s `Display`
+    Pretty,
+}
+
+impl OutputFormat {
+    /// Whether the format is meant for people rather than scripts, an empty result is best
+    /// reported in words for these
+    pub fn is_human_readable(self) -> bool {
+        matches!(self, OutputFormat::Table | OutputFormat::Pretty)
+    }
+}
+
+/// A contact along with the fields that matched a search and how well, if it came from one
+struct Row<'a> {
+    contact: &'a Contact,
+    fields: Option<&'a [ContactField]>,
+    score: Option<f64>,
+}
+
+impl<'a> Row<'a> {
+    fn new(contact: &'a Contact) -> Row<'a> {
+        Row {
+            contact,
+            fields: None,
+            score: None,
+        }
+    }
+}
+
+/// A row for json output
+#[derive(Serialize)]
+struct JsonContact<'a> {
+    #[serde(flatten)]
+    contact: &'a Contact,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matched: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+}
+
+impl<'a> JsonContact<'a> {
+    fn new(row: &Row<'a>) -> JsonContact<'a> {
+        JsonContact {
+            contact: row.contact,
+            matched: row
+                .fields
+                .map(|fields| fields.iter().map(ContactField::to_string).collect()),
+            score: row.score.map(rounded),
+        }
+    }
+}
+
+/// Writes the contacts in `format`
+pub fn write_contacts<'a, W: Write>(
+    writer: W,
+    contacts: impl IntoIterator<Item = &'a Contact>,
+    format: OutputFormat,
+) -> Result<()> {
+    let rows = contacts.into_iter().map(Row::new).collect::<Vec<_>>();
+
+    write_rows(writer, &rows, format)
+}
+
+/// Writes the contacts from a search like [`write_contacts`] along with the fields each one
+/// matched on
+pub fn write_matches<W: Write>(
+    writer: W,
+    matches: &[FieldMatch<'_>],
+    format: OutputFormat,
+) -> Result<()> {
+    let rows = matches
+        .iter()
+        .map(|m| Row {
+            fields: Some(m.fields.as_slice()),
+            ..Row::new(m.contact)
+        })
+        .collect::<Vec<_>>();
+
+    write_rows(writer, &rows, format)
+}
+
+/// Writes the contacts from a ranked search like [`write_matches`] along with each one's score
+pub fn write_ranked<W: Write>(
+    writer: W,
+    matches: &[RankedMatch<'_>],
+    format: OutputFormat,
+) -> Result<()> {
+    let rows = matches
+        .iter()
+        .map(|m| Row {
+            contact: m.contact,
+            fields: Some(m.fields.as_slice()),
+            score: Some(m.score),
+        })
+        .collect::<Vec<_>>();
+
+    write_rows(writer, &rows, format)
+}
+
+fn write_rows<W: Write>(mut writer: W, rows: &[Row<'_>], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Table => write_table(&mut writer, rows)?,
+        OutputFormat::Json => {
+            let values = rows.iter().map(JsonContact::new).collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut writer, &values)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &JsonContact::new(row))?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Csv => {
+            // every number of every contact gets a column, with at least the usual three
+            let phones = rows
+                .iter()
+                .map(|row| row.contact.phone_numbers.len())
+                .fold(3, usize::max);
+            let mapping = ColumnMapping::native_with_phones(phones);
+            let with_matches = rows.iter().any(|row| row.fields.is_some());
+            let with_scores = rows.iter().any(|row| row.score.is_some());
+
+            let mut header = mapping
+                .columns
+                .iter()
+                .map(|(header, _)| header.clone())
+                .collect::<Vec<_>>();
+            if with_matches {
+                header.push("matched".to_string());
+            }
+            if with_scores {
+                header.push("score".to_string());
+            }
+            csv::write_record(&mut writer, &header)?;
+
+            for row in rows {
+                let mut record = mapping
+                    .columns
+                    .iter()
+                    .map(|(_, column)| csv::cell(row.contact, column))
+                    .collect::<Vec<_>>();
+                if with_matches {
+                    record.push(joined(row.fields, " "));
+                }
+                if with_scores {
+                    record.push(score(row.score));
+                }
+                csv::write_record(&mut writer, &record)?;
+            }
+        }
+        OutputFormat::Pretty => {
+            for row in rows {
+                match (row.fields, row.score) {
+                    (Some(fields), Some(score)) => writeln!(
+                        writer,
+                        "{} (matched {}, score {:.2})",
+                        row.contact,
+                        joined(Some(fields), ", "),
+                        score
+                    )?,
+                    (Some(fields), None) => writeln!(
+                        writer,
+                        "{} (matched {})",
+                        row.contact,
+                        joined(Some(fields), ", ")
+                    )?,
+                    _ => writeln!(writer, "{}", row.contact)?,
+                }
+            }
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_table<W: Write>(writer: &mut W, rows: &[Row<'_>]) -> Result<()> {
+    let with_matches = rows.iter().any(|row| row.fields.is_some());
+    let with_scores = rows.iter().any(|row| row.score.is_some());
+
+    let mut header = vec!["First name", "Last name", "Phone numbers", "Address"];
+    if with_matches {
+        header.push("Matched");
+    }
+    if with_scores {
+        header.push("Score");
+    }
+
+    let mut table = vec![header.into_iter().map(String::from).collect::<Vec<_>>()];
+    for row in rows {
+        let contact = row.contact;
+        let numbers = contact
+            .phone_numbers
+            .iter()
+            .map(|number| number.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let address = contact
+            .address
+            .as_ref()
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+
+        let mut cells = vec![
+            contact.first_name.clone(),
+            contact.last_name.clone(),
+            numbers,
+            address,
+        ];
+        if with_matches {
+            cells.push(joined(row.fields, ", "));
+        }
+        if with_scores {
+            cells.push(score(row.score));
+        }
+        table.push(cells);
+    }
+
+    let widths = (0..table[0].len())
+        .map(|column| {
+            table
+                .iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let rule = widths.iter().map(|width| "-".repeat(*width)).collect();
+    table.insert(1, rule);
+
+    for row in table {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join(COLUMN_GAP);
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
+
+fn joined(fields: Option<&[ContactField]>, separator: &str) -> String {
+    fields
+        .unwrap_or_default()
+        .iter()
+        .map(ContactField::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn score(score: Option<f64>) -> String {
+    score
+        .map(|score| format!("{:.2}", score))
+        .unwrap_or_default()
+}
+
+/// Two decimal places are plenty to tell scores apart and keep json free of float noise
+fn rounded(score: f64) -> f64 {
+    (score * 100.0).round() / 100.0
+}
@@ -0,0 +1,234 @@
+use crate::{phonetic, Contact, ContactField, ContactId, MatchPolicy};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Maps the value of every indexed contact field to the ids of the contacts holding that value,
+/// phone numbers are indexed separately in a trie. Each value is stored under its key for every
+/// [`MatchPolicy`] so a search with any policy is a single lookup. The values of each field are
+/// kept in order so a prefix search only visits the values starting with the prefix
+#[derive(Debug, Default)]
+pub(crate) struct FieldIndex {
+    fields: HashMap<(ContactField, MatchPolicy), BTreeMap<String, HashSet<ContactId>>>,
+    /// Soundex keys of the first and last names
+    phonetic: HashMap<(ContactField, String), HashSet<ContactId>>,
+}
+
+impl FieldIndex {
+    pub(crate) fn insert(&mut self, contact: &Contact) {
+        for (field, value) in indexed_fields(contact) {
+            for policy in MatchPolicy::ALL {
+                self.fields
+                    .entry((field, policy))
+                    .or_default()
+                    .entry(policy.key(value))
+                    .or_default()
+                    .insert(contact.id);
+            }
+        }
+
+        for (field, key) in phonetic_keys(contact) {
+            self.phonetic
+                .entry((field, key))
+                .or_default()
+                .insert(contact.id);
+        }
+    }
+
+    /// Removes every entry for the contact, values left without any contacts are dropped so no
+    /// data about a deleted contact lingers in the index
+    pub(crate) fn remove(&mut self, contact: &Contact) {
+        for (field, value) in indexed_fields(contact) {
+            for policy in MatchPolicy::ALL {
+                let values = match self.fields.get_mut(&(field, policy)) {
+                    Some(values) => values,
+                    None => continue,
+                };
+
+                let key = policy.key(value);
+                if let Some(ids) = values.get_mut(&key) {
+                    ids.remove(&contact.id);
+
+                    if ids.is_empty() {
+                        values.remove(&key);
+                    }
+                }
+            }
+        }
+
+        for (field, key) in phonetic_keys(contact) {
+            let entry = (field, key);
+            if let Some(ids) = self.phonetic.get_mut(&entry) {
+                ids.remove(&contact.id);
+
+                if ids.is_empty() {
+                    self.phonetic.remove(&entry);
+                }
+            }
+        }
+    }
+
+    /// Whether no value of any contact is left in the index
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.values().all(BTreeMap::is_empty) && self.phonetic.is_empty()
+    }
+
+    /// Ids of every contact with `field` matching `value` under `policy`
+    pub(crate) fn get(
+        &self,
+        field: ContactField,
+        value: &str,
+        policy: MatchPolicy,
+    ) -> impl Iterator<Item = &ContactId> {
+        self.fields
+            .get(&(field, policy))
+            .and_then(|values| values.get(&policy.key(value)))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Ids of every contact with `field` starting with `prefix` under `policy`
+    pub(crate) fn get_prefix(
+        &self,
+        field: ContactField,
+        prefix: &str,
+        policy: MatchPolicy,
+    ) -> impl Iterator<Item = &ContactId> {
+        let prefix = policy.key(prefix);
+
+        self.fields
+            .get(&(field, policy))
+            .map(|values| values.range(prefix.clone()..))
+            .into_iter()
+            .flatten()
+            .take_while(move |(value, _)| value.starts_with(&prefix))
+            .flat_map(|(_, ids)| ids)
+    }
+
+    /// Ids of every contact with `field` containing `part` under `policy`, every value of the
+    /// field is checked so this is slower than looking up a single value or prefix
+    pub(crate) fn get_containing(
+        &self,
+        field: ContactField,
+        part: &str,
+        policy: MatchPolicy,
+    ) -> impl Iterator<Item = &ContactId> {
+        let part = policy.key(part);
+
+        self.fields
+            .get(&(field, policy))
+            .into_iter()
+            .flatten()
+            .filter(move |(value, _)| value.contains(&part))
+            .flat_map(|(_, ids)| ids)
+    }
+
+    /// Ids of every contact with a first or last name, as given by `field`, sounding like `name`
+    pub(crate) fn get_phonetic(
+        &self,
+        field: ContactField,
+        name: &str,
+    ) -> impl Iterator<Item = &ContactId> {
+        phonetic::soundex(name)
+            .and_then(|key| self.phonetic.get(&(field, key)))
+            .into_iter()
+            .flatten()
+    }
+}
+
+fn indexed_fields(contact: &Contact) -> impl Iterator<Item = (ContactField, &str)> {
+    contact
+        .fields()
+        .into_iter()
+        .filter(|(field, _)| *field != ContactField::PhoneNumber)
+}
+
+fn phonetic_keys(contact: &Contact) -> impl Iterator<Item = (ContactField, String)> + '_ {
+    [
+        (ContactField::FirstName, &contact.first_name),
+        (ContactField::LastName, &contact.last_name),
+    ]
+    .into_iter()
+    .filter_map(|(field, name)| Some((field, phonetic::soundex(name)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LabelledNumber, PhoneLabel};
+
+    fn contact(first_name: &str, last_name: &str, number: &str) -> Contact {
+        let number = LabelledNumber {
+            label: PhoneLabel::Mobile,
+            number: number.parse().unwrap(),
+            primary: true,
+        };
+
+        Contact::new(
+            first_name.to_string(),
+            last_name.to_string(),
+            vec![number],
+            None,
+        )
+    }
+
+    /// The ids found sorted so they can be compared
+    fn sorted<'a>(ids: impl Iterator<Item = &'a ContactId>) -> Vec<ContactId> {
+        let mut ids = ids.copied().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn prefix_lookups_only_match_values_starting_with_the_prefix() {
+        let smith = contact("John", "Smith", "5550000001");
+        let smithers = contact("Jane", "Smithers", "5550000002");
+        let small = contact("Anne", "Small", "5550000003");
+        let mut index = FieldIndex::default();
+        for contact in [&smith, &smithers, &small] {
+            index.insert(contact);
+        }
+
+        let prefix =
+            |value, policy| sorted(index.get_prefix(ContactField::LastName, value, policy));
+        assert_eq!(
+            prefix("Smi", MatchPolicy::Exact),
+            sorted([smith.id, smithers.id].iter())
+        );
+        assert_eq!(
+            prefix("Sm", MatchPolicy::Exact),
+            sorted([smith.id, smithers.id, small.id].iter())
+        );
+        assert_eq!(prefix("smith", MatchPolicy::Exact), []);
+        assert_eq!(
+            prefix("smithe", MatchPolicy::CaseInsensitive),
+            [smithers.id]
+        );
+        assert_eq!(prefix("Smz", MatchPolicy::Exact), []);
+    }
+
+    #[test]
+    fn removing_a_contact_keeps_values_shared_with_others() {
+        let john = contact("John", "Smith", "5550000001");
+        let mary = contact("Mary", "Smith", "5550000002");
+        let mut index = FieldIndex::default();
+        index.insert(&john);
+        index.insert(&mary);
+
+        index.remove(&john);
+        let smiths = sorted(index.get(ContactField::LastName, "smith", MatchPolicy::Normalized));
+        assert_eq!(smiths, [mary.id]);
+        assert_eq!(
+            index
+                .get(ContactField::FirstName, "John", MatchPolicy::Exact)
+                .count(),
+            0
+        );
+        assert_eq!(
+            index.get_phonetic(ContactField::FirstName, "Jon").count(),
+            0
+        );
+
+        index.remove(&mary);
+        assert!(index.is_empty());
+    }
+}
@@ -1,15 +1,54 @@
-use anyhow::{anyhow, Result};
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+mod atomic;
+mod collation;
+mod contact_id;
+pub mod csv;
+mod error;
+pub mod format;
+mod index;
+mod lock;
+mod matching;
+mod name_query;
+mod patch;
+mod phone_number;
+mod phonetic;
+pub mod query;
+mod region;
+mod search;
+pub mod storage;
+mod t9;
+#[cfg(test)]
+mod test_support;
+mod trie;
+pub mod vcard;
+
+pub use contact_id::ContactId;
+use csv::ColumnMapping;
+pub use error::{PhoneBookError, Result};
+use index::FieldIndex;
+pub use lock::{FileLock, LockMode};
+pub use matching::MatchPolicy;
+pub use name_query::{NameMatch, NameQuery, NameTarget};
+pub use patch::{Change, ContactPatch};
+pub use phone_number::PhoneNumber;
+use query::{Query, Term};
+pub use region::{Region, REGIONS};
+pub use search::{RankedMatch, SearchOptions};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
 use std::ffi::OsStr;
-use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::io::BufReader;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
 use std::path::Path;
+use std::str::FromStr;
+use storage::{JsonStorage, Snapshot, Storage};
+use t9::T9Index;
+use trie::DigitTrie;
+use vcard::VCardVersion;
 
-pub type PhoneNumber = String;
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Address {
     pub street_address: String,
     pub city: String,
@@ -18,142 +57,1266 @@ pub struct Address {
     pub country: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl Address {
+    /// Every component of the address paired with the field it belongs to
+    pub fn fields(&self) -> [(ContactField, &str); 5] {
+        [
+            (ContactField::StreetAddress, self.street_address.as_str()),
+            (ContactField::City, self.city.as_str()),
+            (ContactField::State, self.state.as_str()),
+            (ContactField::Postcode, self.postcode.as_str()),
+            (ContactField::Country, self.country.as_str()),
+        ]
+    }
+}
+
+/// Parses `[street address], [city], [state/province], [postcode], [country]`. Parts can be
+/// quoted or have commas escaped with a backslash to include a comma, and whitespace is trimmed
+/// and collapsed. Extra leading parts are kept in the street address, so
+/// `Unit 4, 12 Smith St, Melbourne, VIC, 3000, Australia` has the street address
+/// `Unit 4, 12 Smith St`. Any part can be left empty and an address with fewer than five parts
+/// leaves the trailing parts empty
+impl FromStr for Address {
+    type Err = PhoneBookError;
+
+    fn from_str(s: &str) -> Result<Address> {
+        let invalid = |reason| PhoneBookError::InvalidAddress {
+            address: s.to_string(),
+            reason,
+        };
+
+        let mut parts = Vec::new();
+        let mut part = String::new();
+        let mut quoted = false;
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => part.push(escaped),
+                    None => return Err(invalid("address ends with an unfinished escape")),
+                },
+                '"' => quoted = !quoted,
+                ',' if !quoted => parts.push(std::mem::take(&mut part)),
+                c => part.push(c),
+            }
+        }
+        parts.push(part);
+
+        if quoted {
+            return Err(invalid("address has an unclosed quote"));
+        }
+
+        let mut parts = parts
+            .iter()
+            .map(|part| part.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>();
+
+        if parts.iter().all(String::is_empty) {
+            return Err(invalid("address is empty"));
+        }
+
+        if parts.len() > 5 {
+            let street_parts = parts.len() - 4;
+            let street = parts.drain(..street_parts).collect::<Vec<_>>().join(", ");
+            parts.insert(0, street);
+        }
+
+        let mut parts = parts.into_iter();
+        let mut next = || parts.next().unwrap_or_default();
+
+        Ok(Address {
+            street_address: next(),
+            city: next(),
+            state: next(),
+            postcode: next(),
+            country: next(),
+        })
+    }
+}
+
+/// `[street address], [city], [state] [postcode], [country]` leaving out any empty parts
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let region = format!("{} {}", self.state, self.postcode);
+        let parts = [
+            self.street_address.as_str(),
+            self.city.as_str(),
+            region.trim(),
+            self.country.as_str(),
+        ];
+
+        let parts = parts.into_iter().filter(|part| !part.is_empty());
+        for (index, part) in parts.enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(part)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What a phone number is used for, anything other than the built in labels is kept as a custom
+/// label
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum PhoneLabel {
+    Mobile,
+    Home,
+    Work,
+    Fax,
+    Custom(String),
+}
+
+impl From<String> for PhoneLabel {
+    fn from(label: String) -> PhoneLabel {
+        match label.to_lowercase().as_str() {
+            "mobile" => PhoneLabel::Mobile,
+            "home" => PhoneLabel::Home,
+            "work" => PhoneLabel::Work,
+            "fax" => PhoneLabel::Fax,
+            _ => PhoneLabel::Custom(label),
+        }
+    }
+}
+
+impl From<PhoneLabel> for String {
+    fn from(label: PhoneLabel) -> String {
+        label.to_string()
+    }
+}
+
+impl FromStr for PhoneLabel {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<PhoneLabel, Infallible> {
+        Ok(PhoneLabel::from(s.to_string()))
+    }
+}
+
+impl Display for PhoneLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PhoneLabel::Mobile => f.write_str("mobile"),
+            PhoneLabel::Home => f.write_str("home"),
+            PhoneLabel::Work => f.write_str("work"),
+            PhoneLabel::Fax => f.write_str("fax"),
+            PhoneLabel::Custom(label) => f.write_str(label),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelledNumber {
+    pub label: PhoneLabel,
+    pub number: PhoneNumber,
+    /// The number used to identify the contact, every contact has exactly one primary number
+    pub primary: bool,
+}
+
+/// `[international number] ([label])` with the label followed by `, primary` for the primary
+/// number
+impl Display for LabelledNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.primary {
+            true => write!(
+                f,
+                "{} ({}, primary)",
+                self.number.international(),
+                self.label
+            ),
+            false => write!(f, "{} ({})", self.number.international(), self.label),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "StoredContact")]
 pub struct Contact {
+    pub id: ContactId,
     pub first_name: String,
     pub last_name: String,
-    pub phone_number: PhoneNumber,
+    pub phone_numbers: Vec<LabelledNumber>,
     pub address: Option<Address>,
 }
 
-impl Eq for Contact {}
+/// A contact as it is stored on disk, files written before contacts could have several numbers
+/// hold a single `phone_number` which is migrated to a primary mobile number. Contacts from files
+/// written before contacts had ids are given one derived from their primary number
+#[derive(Deserialize)]
+struct StoredContact {
+    id: Option<ContactId>,
+    first_name: String,
+    last_name: String,
+    #[serde(default)]
+    phone_numbers: Vec<LabelledNumber>,
+    phone_number: Option<String>,
+    address: Option<Address>,
+}
+
+impl TryFrom<StoredContact> for Contact {
+    type Error = PhoneBookError;
+
+    fn try_from(stored: StoredContact) -> Result<Contact> {
+        let mut phone_numbers = stored.phone_numbers;
+
+        // the single number of older files was a ten digit US number, whatever region new
+        // numbers are entered in
+        let number = stored
+            .phone_number
+            .map(|number| PhoneNumber::parse(&number, Region::default_region()))
+            .transpose()?;
+
+        if let Some(number) = number {
+            if phone_numbers
+                .iter()
+                .all(|existing| existing.number != number)
+            {
+                phone_numbers.insert(
+                    0,
+                    LabelledNumber {
+                        label: PhoneLabel::Mobile,
+                        number,
+                        primary: phone_numbers.iter().all(|existing| !existing.primary),
+                    },
+                );
+            }
+        }
+
+        // a contact without any numbers is rejected when it is inserted, whatever its id
+        let legacy_number = phone_numbers
+            .iter()
+            .find(|number| number.primary)
+            .or(phone_numbers.first());
+        let id = match (stored.id, legacy_number) {
+            (Some(id), _) => id,
+            (None, Some(number)) => ContactId::from_legacy_number(&number.number),
+            (None, None) => ContactId::generate(),
+        };
+
+        Ok(Contact {
+            id,
+            first_name: stored.first_name,
+            last_name: stored.last_name,
+            phone_numbers,
+            address: stored.address,
+        })
+    }
+}
+
+impl Contact {
+    /// Creates a contact with a newly generated id
+    pub fn new(
+        first_name: String,
+        last_name: String,
+        phone_numbers: Vec<LabelledNumber>,
+        address: Option<Address>,
+    ) -> Contact {
+        Contact {
+            id: ContactId::generate(),
+            first_name,
+            last_name,
+            phone_numbers,
+            address,
+        }
+    }
+
+    /// The number marked as primary, this is only `None` for contacts that have not been
+    /// validated by being inserted into a phone book
+    pub fn primary_number(&self) -> Option<&PhoneNumber> {
+        self.phone_numbers
+            .iter()
+            .find(|number| number.primary)
+            .map(|number| &number.number)
+    }
+
+    pub fn numbers(&self) -> impl Iterator<Item = &PhoneNumber> {
+        self.phone_numbers.iter().map(|number| &number.number)
+    }
+
+    /// Every searchable field of the contact paired with its value, address fields are only
+    /// included when the contact has an address
+    pub fn fields(&self) -> Vec<(ContactField, &str)> {
+        let mut fields = vec![
+            (ContactField::FirstName, self.first_name.as_str()),
+            (ContactField::LastName, self.last_name.as_str()),
+        ];
+
+        fields.extend(
+            self.numbers()
+                .map(|number| (ContactField::PhoneNumber, number.as_str())),
+        );
+
+        if let Some(address) = &self.address {
+            fields.extend(address.fields());
+        }
+
+        fields
+    }
+
+    /// Checks the contact has at least one number, exactly one primary number and doesn't list
+    /// the same number twice
+    fn validate(&self) -> Result<()> {
+        let invalid = |reason| PhoneBookError::InvalidContact { reason };
+
+        let mut primary_numbers = self.phone_numbers.iter().filter(|number| number.primary);
+        match (primary_numbers.next(), primary_numbers.next()) {
+            (Some(_), None) => {}
+            (None, _) if self.phone_numbers.is_empty() => {
+                return Err(invalid("contact must have at least one phone number"))
+            }
+            (None, _) => return Err(invalid("contact must have a primary phone number")),
+            (Some(_), Some(_)) => {
+                return Err(invalid("contact can only have one primary phone number"))
+            }
+        }
+
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = self.numbers().find(|number| !seen.insert(*number)) {
+            return Err(PhoneBookError::DuplicatePhoneNumber(duplicate.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// The name, phone numbers and address on one line, `Ann Lee: +1 555-123-4567 (mobile, primary);
+/// 1 Main St, Springfield, IL 62701, USA`
+impl Display for Contact {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = format!("{} {}", self.first_name, self.last_name);
+        f.write_str(name.trim())?;
+
+        for (index, number) in self.phone_numbers.iter().enumerate() {
+            f.write_str(if index == 0 { ": " } else { ", " })?;
+            write!(f, "{}", number)?;
+        }
+
+        if let Some(address) = &self.address {
+            write!(f, "; {}", address)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Names every field of a contact that can be searched
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContactField {
+    FirstName,
+    LastName,
+    PhoneNumber,
+    StreetAddress,
+    City,
+    State,
+    Postcode,
+    Country,
+}
+
+impl ContactField {
+    pub const ALL: [ContactField; 8] = [
+        ContactField::FirstName,
+        ContactField::LastName,
+        ContactField::PhoneNumber,
+        ContactField::StreetAddress,
+        ContactField::City,
+        ContactField::State,
+        ContactField::Postcode,
+        ContactField::Country,
+    ];
+}
 
-impl PartialEq for Contact {
-    fn eq(&self, other: &Self) -> bool {
-        self.phone_number == other.phone_number
+/// The field's name in snake case, as used in CSV headers
+impl Display for ContactField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContactField::FirstName => "first_name",
+            ContactField::LastName => "last_name",
+            ContactField::PhoneNumber => "phone_number",
+            ContactField::StreetAddress => "street_address",
+            ContactField::City => "city",
+            ContactField::State => "state",
+            ContactField::Postcode => "postcode",
+            ContactField::Country => "country",
+        })
     }
 }
 
-impl Hash for Contact {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.phone_number.hash(state);
+/// What [`PhoneBook::iter_sorted`] orders contacts by, names are compared ignoring case and
+/// accents before anything else. The ordering doesn't depend on the locale, accented letters
+/// always sort with their base letter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// Last name then first name
+    LastName,
+    /// First name then last name
+    FirstName,
+    /// City then last and first name, contacts without an address come after every city
+    City,
+    /// Primary number in E.164 form then last and first name
+    Phone,
+}
+
+impl SortKey {
+    /// The contact's position under the key, build it once per contact when sorting as it folds
+    /// every name compared
+    fn key(self, contact: &Contact) -> Vec<SortPart> {
+        let last = || SortPart::Text(collation::key(&contact.last_name));
+        let first = || SortPart::Text(collation::key(&contact.first_name));
+
+        match self {
+            SortKey::LastName => vec![last(), first()],
+            SortKey::FirstName => vec![first(), last()],
+            SortKey::City => {
+                let city = match &contact.address {
+                    Some(address) => SortPart::Text(collation::key(&address.city)),
+                    None => SortPart::Missing,
+                };
+                vec![city, last(), first()]
+            }
+            SortKey::Phone => vec![
+                SortPart::Number(contact.primary_number().cloned()),
+                last(),
+                first(),
+            ],
+        }
     }
+
+    fn compare(self, a: &Contact, b: &Contact) -> Ordering {
+        self.key(a).cmp(&self.key(b))
+    }
+}
+
+/// Orders the contacts by `key`, building each contact's key once. Contacts that are equal on
+/// every part of the key are ordered by id
+fn sorted(
+    mut contacts: Vec<&Contact>,
+    key: SortKey,
+    order: SortOrder,
+) -> std::vec::IntoIter<&Contact> {
+    match order {
+        SortOrder::Ascending => contacts.sort_by_cached_key(|c| (key.key(c), c.id)),
+        SortOrder::Descending => contacts.sort_by_cached_key(|c| (Reverse(key.key(c)), c.id)),
+    }
+
+    contacts.into_iter()
+}
+
+/// One part of a [`SortKey`], parts in the same position are always the same variant except for
+/// a missing city which sorts after every city
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortPart {
+    Text(collation::Key),
+    Missing,
+    Number(Option<PhoneNumber>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// A contact returned by [`PhoneBook::find_any`] along with the fields that matched the search
+#[derive(Debug)]
+pub struct FieldMatch<'a> {
+    pub contact: &'a Contact,
+    pub fields: Vec<ContactField>,
+}
+
+/// What happened to each record read by an import such as [`PhoneBook::import_vcard`], for a
+/// dry run `imported` counts the records that would have been imported
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: Vec<SkippedRecord>,
+}
+
+/// A record that was not imported, `name` is empty if the record couldn't be read far enough to
+/// find one
+#[derive(Debug)]
+pub struct SkippedRecord {
+    pub line: usize,
+    pub name: String,
+    pub error: PhoneBookError,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "Snapshot<Contact>")]
 pub struct PhoneBook {
-    contacts: HashSet<Contact>,
+    #[serde(serialize_with = "serialize_contacts")]
+    contacts: HashMap<ContactId, Contact>,
+    #[serde(skip)]
+    phone_index: DigitTrie<ContactId>,
+    #[serde(skip)]
+    field_index: FieldIndex,
+    #[serde(skip)]
+    t9_index: T9Index,
+    /// Where numbers searched for and imported without a country calling code are from
+    #[serde(skip)]
+    region: &'static Region,
+    /// Where changes are written through to when the phone book was opened from storage
+    #[serde(skip)]
+    storage: Option<Box<dyn Storage>>,
+}
+
+/// Contacts are written as a list as the ids are already stored in each contact
+fn serialize_contacts<S: Serializer>(
+    contacts: &HashMap<ContactId, Contact>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(contacts.values())
+}
+
+impl TryFrom<Snapshot<Contact>> for PhoneBook {
+    type Error = PhoneBookError;
+
+    fn try_from(stored: Snapshot<Contact>) -> Result<PhoneBook> {
+        let mut phone_book = PhoneBook::new();
+
+        for contact in stored.contacts {
+            phone_book.insert_contact(contact)?;
+        }
+
+        Ok(phone_book)
+    }
+}
+
+impl Default for PhoneBook {
+    fn default() -> PhoneBook {
+        PhoneBook::new()
+    }
 }
 
 impl PhoneBook {
     pub fn new() -> PhoneBook {
         PhoneBook {
-            contacts: HashSet::new(),
+            contacts: HashMap::new(),
+            phone_index: DigitTrie::default(),
+            field_index: FieldIndex::default(),
+            t9_index: T9Index::default(),
+            region: Region::default_region(),
+            storage: None,
         }
     }
 
-    pub fn new_from_file(path: &OsStr) -> Result<PhoneBook> {
-        let path = Path::new(path);
+    /// The region numbers without a country calling code are read in when searching and
+    /// importing, the US unless changed with [`PhoneBook::set_region`]
+    pub fn region(&self) -> &'static Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: &'static Region) {
+        self.region = region;
+    }
+
+    /// Loads every contact from the storage, after this every change made to the phone book is
+    /// also made to the storage and is persisted by [`PhoneBook::commit`]
+    pub fn open(mut storage: Box<dyn Storage>) -> Result<PhoneBook> {
+        let mut phone_book = PhoneBook::new();
 
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
+        for contact in storage.load()? {
+            phone_book.insert_contact(contact)?;
+        }
+        phone_book.storage = Some(storage);
 
-        let u = serde_json::from_reader(reader)?;
+        Ok(phone_book)
+    }
 
-        Ok(u)
+    /// Persists every change made since the phone book was opened, this does nothing for phone
+    /// books that weren't opened from storage
+    pub fn commit(&mut self) -> Result<()> {
+        match self.storage.as_mut() {
+            Some(storage) => storage.commit(&mut self.contacts.values()),
+            None => Ok(()),
+        }
     }
 
+    /// Loads a json phone book, replaying any journal left next to it by the journal storage
+    /// backend. The phone book is not attached to the storage, changes are only kept by saving
+    pub fn new_from_file(path: &OsStr) -> Result<PhoneBook> {
+        let storage = JsonStorage::open(Path::new(path), false)?;
+
+        let mut phone_book = PhoneBook::open(Box::new(storage))?;
+        phone_book.storage = None;
+
+        Ok(phone_book)
+    }
+
+    /// Writes the phone book to a temporary file and renames it over `path`, so a failed save
+    /// leaves the previous version intact
     pub fn save_to_file(&self, path: &OsStr) -> Result<()> {
-        let path = Path::new(path);
+        self.write_file(path, false)
+    }
 
-        let file = File::create(&path)?;
+    /// Saves like [`PhoneBook::save_to_file`] but also keeps the previous version of the file
+    /// next to it with a `.bak` extension
+    pub fn save_to_file_with_backup(&self, path: &OsStr) -> Result<()> {
+        self.write_file(path, true)
+    }
 
-        serde_json::to_writer_pretty(file, self)?;
+    /// Rewrites the storage the phone book was opened from in its most compact form, folding a
+    /// journal into its snapshot. This does nothing for phone books that weren't opened from
+    /// storage
+    pub fn compact(&mut self) -> Result<()> {
+        match self.storage.as_mut() {
+            Some(storage) => storage.compact(&mut self.contacts.values()),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds every contact from the vCards in `input`. Cards that can't be read or that have
+    /// invalid numbers or numbers already in the phone book are skipped and listed in the report,
+    /// only a file that isn't a vCard file at all or a failure to store a contact is an error
+    pub fn import_vcard(&mut self, input: &str) -> Result<ImportReport> {
+        let cards = vcard::read(input, self.region)?
+            .into_iter()
+            .map(|card| (card.line, card.name, card.contact));
+
+        self.import(cards, false)
+    }
+
+    /// Adds every contact from the rows of a CSV file, skipping rows like
+    /// [`PhoneBook::import_vcard`] skips cards. A dry run reports what would be skipped, including
+    /// rows that collide with earlier rows, without changing the phone book
+    pub fn import_csv(
+        &mut self,
+        input: &str,
+        mapping: &ColumnMapping,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let rows = csv::read(input, mapping, self.region)?
+            .into_iter()
+            .map(|row| (row.line, row.name, row.contact));
+
+        self.import(rows, dry_run)
+    }
+
+    /// Writes every contact as a vCard, ordered by last name then first name
+    pub fn export_vcard<W: Write>(&self, writer: W, version: VCardVersion) -> Result<()> {
+        vcard::write(
+            writer,
+            self.iter_sorted(SortKey::LastName, SortOrder::Ascending),
+            version,
+        )
+    }
+
+    /// Writes every contact as a CSV row, ordered by last name then first name. Numbers that
+    /// don't have a column in the mapping are left out
+    pub fn export_csv<W: Write>(&self, writer: W, mapping: &ColumnMapping) -> Result<()> {
+        csv::write(
+            writer,
+            self.iter_sorted(SortKey::LastName, SortOrder::Ascending),
+            mapping,
+        )
+    }
+
+    /// Every contact ordered by `key`, contacts that are equal on every part of the key are
+    /// ordered by id so the order is the same every time
+    pub fn iter_sorted(&self, key: SortKey, order: SortOrder) -> impl Iterator<Item = &Contact> {
+        sorted(self.contacts.values().collect(), key, order)
+    }
+
+    /// Like [`PhoneBook::iter_sorted`] but only the contacts matching `query`, with names and
+    /// address fields compared under `policy`
+    pub fn iter_sorted_matching(
+        &self,
+        query: &Query,
+        policy: MatchPolicy,
+        key: SortKey,
+        order: SortOrder,
+    ) -> Result<impl Iterator<Item = &Contact>> {
+        let ids = self.query_ids(query, policy)?;
+
+        Ok(sorted(self.contacts_for(ids.into_iter()), key, order))
+    }
+
+    /// Inserts each record's contact, or only checks that it could be inserted for a dry run
+    fn import(
+        &mut self,
+        records: impl IntoIterator<Item = (usize, String, Result<Contact>)>,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        // a dry run doesn't insert anything so collisions between records are tracked here
+        let mut seen_ids = HashSet::new();
+        let mut seen_numbers = HashSet::new();
+
+        for (line, name, contact) in records {
+            let result = contact.and_then(|contact| {
+                if !dry_run {
+                    return self.insert_contact(contact);
+                }
+
+                self.check_insert(&contact)?;
+                if !seen_ids.insert(contact.id) {
+                    return Err(PhoneBookError::DuplicateContactId(contact.id));
+                }
+                if let Some(number) = contact.numbers().find(|n| seen_numbers.contains(*n)) {
+                    return Err(PhoneBookError::DuplicatePhoneNumber(number.clone()));
+                }
+                seen_numbers.extend(contact.numbers().cloned());
+
+                Ok(())
+            });
+
+            match result {
+                Ok(()) => report.imported += 1,
+                Err(error @ (PhoneBookError::Io(_) | PhoneBookError::Storage(_))) => {
+                    return Err(error)
+                }
+                Err(error) => report.skipped.push(SkippedRecord { line, name, error }),
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn write_file(&self, path: &OsStr, backup: bool) -> Result<()> {
+        storage::save_snapshot(Path::new(path), backup, self.contacts.values())?;
 
         Ok(())
     }
 
     pub fn insert_contact(&mut self, contact: Contact) -> Result<()> {
-        is_valid_phone_number(contact.phone_number.as_str())?;
+        self.check_insert(&contact)?;
 
-        if self.contacts.insert(contact.clone()) != true {
-            return Err(anyhow!("number already exists unable insert"));
+        if let Some(storage) = self.storage.as_mut() {
+            storage.put(&contact)?;
         }
+        self.index_contact(&contact)?;
+        self.contacts.insert(contact.id, contact);
 
         Ok(())
     }
 
+    /// Checks that the contact is valid and that neither its id nor its numbers are already used
+    fn check_insert(&self, contact: &Contact) -> Result<()> {
+        contact.validate()?;
+
+        if self.contacts.contains_key(&contact.id) {
+            return Err(PhoneBookError::DuplicateContactId(contact.id));
+        }
+
+        if let Some(number) = contact
+            .numbers()
+            .find(|number| self.phone_index.get(number.digits()).is_some())
+        {
+            return Err(PhoneBookError::DuplicatePhoneNumber(number.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the contact with the same id, its phone numbers can be changed freely as long as
+    /// they aren't used by another contact
     pub fn replace_contact(&mut self, contact: Contact) -> Result<()> {
-        is_valid_phone_number(contact.phone_number.as_str())?;
+        contact.validate()?;
 
-        return match self.contacts.replace(contact) {
-            Some(_) => Ok(()),
-            None => Err(anyhow!("unable to update contact")),
-        };
+        if !self.contacts.contains_key(&contact.id) {
+            return Err(PhoneBookError::ContactIdNotFound(contact.id));
+        }
+
+        let conflict = contact.numbers().find(|number| {
+            self.phone_index
+                .get(number.digits())
+                .is_some_and(|owner| *owner != contact.id)
+        });
+        if let Some(number) = conflict {
+            return Err(PhoneBookError::DuplicatePhoneNumber(number.clone()));
+        }
+
+        if let Some(storage) = self.storage.as_mut() {
+            storage.put(&contact)?;
+        }
+        if let Some(existing) = self.contacts.remove(&contact.id) {
+            self.unindex_contact(&existing);
+        }
+        self.index_contact(&contact)?;
+        self.contacts.insert(contact.id, contact);
+
+        Ok(())
     }
 
-    pub fn delete_contact(&mut self, number: String) -> Result<()> {
-        is_valid_phone_number(number.as_str())?;
+    /// Makes every change in the patch to the contact with this id, nothing is changed if the
+    /// patched contact is invalid or has a number used by another contact
+    pub fn patch_contact(&mut self, id: ContactId, patch: ContactPatch) -> Result<()> {
+        let mut contact = self.find_id(id)?.clone();
+        patch.apply(&mut contact)?;
+
+        self.replace_contact(contact)
+    }
 
-        if self.contacts.remove(&Contact {
-            first_name: "".to_string(),
-            last_name: "".to_string(),
-            phone_number: number,
-            address: None,
-        }) {
+    /// Changes one of a contact's phone numbers keeping its label and whether it is the primary
+    /// number, the new number must not be used by any contact. Changing a number to itself does
+    /// nothing
+    pub fn change_phone_number(&mut self, old: PhoneNumber, new: PhoneNumber) -> Result<()> {
+        let mut contact = self.find_phone_number(old.clone())?.clone();
+
+        if old == new {
             return Ok(());
-        };
+        }
+
+        if self.phone_index.get(new.digits()).is_some() {
+            return Err(PhoneBookError::DuplicatePhoneNumber(new));
+        }
+
+        for number in contact.phone_numbers.iter_mut() {
+            if number.number == old {
+                number.number = new.clone();
+            }
+        }
 
-        Err(anyhow!("unable to delete contact"))
+        self.replace_contact(contact)
     }
 
-    pub fn find_phone_number(&self, number: String) -> Result<&Contact> {
-        is_valid_phone_number(number.as_str())?;
+    /// Deletes the contact that has the given phone number, this can be any of its numbers
+    pub fn delete_contact(&mut self, number: PhoneNumber) -> Result<()> {
+        let id = match self.phone_index.get(number.digits()) {
+            Some(id) => *id,
+            None => return Err(PhoneBookError::ContactNotFound(number)),
+        };
+
+        if let Some(storage) = self.storage.as_mut() {
+            storage.delete(id)?;
+        }
+        if let Some(contact) = self.contacts.remove(&id) {
+            self.unindex_contact(&contact);
+            return Ok(());
+        };
 
+        Err(PhoneBookError::ContactNotFound(number))
+    }
+
+    pub fn find_id(&self, id: ContactId) -> Result<&Contact> {
         self.contacts
-            .iter()
-            .find(|contact| contact.phone_number == number)
-            .ok_or(anyhow!("no contact found"))
+            .get(&id)
+            .ok_or(PhoneBookError::ContactIdNotFound(id))
     }
 
-    pub fn find_name(&self, first: Option<String>, last: Option<String>) -> Vec<&Contact> {
-        if first.is_some() && last.is_some() {
-            return self
-                .contacts
-                .iter()
-                .filter(|contact| {
-                    contact.first_name == first.clone().unwrap_or_default()
-                        && contact.last_name == last.clone().unwrap_or_default()
-                })
-                .collect::<Vec<_>>();
-        } else {
-            return self
-                .contacts
-                .iter()
-                .filter(|contact| {
-                    contact.first_name == first.clone().unwrap_or_default()
-                        || contact.last_name == last.clone().unwrap_or_default()
-                })
-                .collect::<Vec<_>>();
+    /// Looks up the contact that has the given phone number, this can be any of its numbers
+    pub fn find_phone_number(&self, number: PhoneNumber) -> Result<&Contact> {
+        self.phone_index
+            .get(number.digits())
+            .and_then(|id| self.contacts.get(id))
+            .ok_or(PhoneBookError::ContactNotFound(number))
+    }
+
+    /// Looks up every contact with names matching the query, sorted by last name
+    pub fn find_name(&self, query: &NameQuery) -> Vec<&Contact> {
+        let ids = match query.target() {
+            NameTarget::First(first) => self.name_ids(ContactField::FirstName, first, query),
+            NameTarget::Last(last) => self.name_ids(ContactField::LastName, last, query),
+            NameTarget::Both { first, last } => {
+                let last = self.name_ids(ContactField::LastName, last, query);
+                self.name_ids(ContactField::FirstName, first, query)
+                    .intersection(&last)
+                    .copied()
+                    .collect()
+            }
+            NameTarget::Either(name) => {
+                let mut ids = self.name_ids(ContactField::FirstName, name, query);
+                ids.extend(self.name_ids(ContactField::LastName, name, query));
+                ids
+            }
+        };
+
+        self.contacts_by_last_name(ids.into_iter())
+    }
+
+    fn name_ids(&self, field: ContactField, name: &str, query: &NameQuery) -> HashSet<&ContactId> {
+        let policy = query.policy();
+
+        match query.mode() {
+            NameMatch::Exact => self.field_index.get(field, name, policy).collect(),
+            NameMatch::Prefix => self.field_index.get_prefix(field, name, policy).collect(),
+            NameMatch::Contains => self
+                .field_index
+                .get_containing(field, name, policy)
+                .collect(),
         }
     }
 
     pub fn find_city(&self, city: String) -> Vec<&Contact> {
-        self.contacts
-            .iter()
-            //TODO try and not clone this?
-            .filter(|contact| match contact.address.clone() {
-                Some(address) => address.city == city,
-                _ => false,
+        self.find_city_matching(&city, MatchPolicy::Exact)
+    }
+
+    /// Like [`PhoneBook::find_city`] with the city compared under `policy`. Contacts are returned
+    /// sorted by last name
+    pub fn find_city_matching(&self, city: &str, policy: MatchPolicy) -> Vec<&Contact> {
+        self.contacts_by_last_name(self.field_index.get(ContactField::City, city, policy))
+    }
+
+    /// Looks up every contact whose names sound like the given ones, so misspelt names are still
+    /// found. When both names are given a contact has to sound like both. Results are ranked by
+    /// how few letters differ from the given names, closest first
+    pub fn find_name_phonetic(&self, first: Option<&str>, last: Option<&str>) -> Vec<&Contact> {
+        let names = [
+            (ContactField::FirstName, first),
+            (ContactField::LastName, last),
+        ]
+        .into_iter()
+        .filter_map(|(field, name)| Some((field, name?)))
+        .collect::<Vec<_>>();
+
+        let mut ids: Option<HashSet<&ContactId>> = None;
+        for (field, name) in &names {
+            let matches = self.field_index.get_phonetic(*field, name).collect();
+            ids = Some(match ids {
+                Some(ids) => ids.intersection(&matches).copied().collect(),
+                None => matches,
+            });
+        }
+
+        let distance = |contact: &Contact| -> usize {
+            names
+                .iter()
+                .map(|(field, name)| {
+                    let value = match field {
+                        ContactField::FirstName => &contact.first_name,
+                        _ => &contact.last_name,
+                    };
+                    let key = |s: &str| MatchPolicy::Normalized.key(s);
+                    search::edit_distance(&key(name), &key(value))
+                })
+                .sum()
+        };
+
+        let mut contacts = self.contacts_for(ids.unwrap_or_default().into_iter());
+        contacts.sort_by_cached_key(|c| (distance(c), SortKey::LastName.key(c), c.id));
+
+        contacts
+    }
+
+    /// Looks up every contact that has a field exactly equal to the search value, the search is
+    /// run against the name, phone number and every address field. Contacts are returned sorted
+    /// by last name
+    pub fn find_any(&self, search: &str) -> Vec<FieldMatch<'_>> {
+        self.find_any_matching(search, MatchPolicy::Exact)
+    }
+
+    /// Like [`PhoneBook::find_any`] with every field other than the phone number compared under
+    /// `policy`, phone numbers are always compared by their digits
+    pub fn find_any_matching(&self, search: &str, policy: MatchPolicy) -> Vec<FieldMatch<'_>> {
+        let mut matches: HashMap<&ContactId, Vec<ContactField>> = HashMap::new();
+
+        for field in ContactField::ALL {
+            if field == ContactField::PhoneNumber {
+                let id = PhoneNumber::parse(search, self.region)
+                    .ok()
+                    .and_then(|number| self.phone_index.get(number.digits()));
+
+                if let Some(id) = id {
+                    matches.entry(id).or_default().push(field);
+                }
+                continue;
+            }
+
+            for id in self.field_index.get(field, search, policy) {
+                matches.entry(id).or_default().push(field);
+            }
+        }
+
+        let mut matches = matches
+            .into_iter()
+            .filter_map(|(id, fields)| {
+                let contact = self.contacts.get(id)?;
+                Some(FieldMatch { contact, fields })
             })
+            .collect::<Vec<_>>();
+        matches.sort_by_cached_key(|m| (SortKey::LastName.key(m.contact), m.contact.id));
+
+        matches
+    }
+
+    /// Looks up every contact with a name, city or street address within a few typos of the
+    /// search, ranked by how closely each one matches
+    pub fn search_ranked(&self, search: &str, options: SearchOptions) -> Vec<RankedMatch<'_>> {
+        search::rank(self.contacts.values(), search, options)
+    }
+
+    /// Looks up every contact matching a [`Query`], with names and address fields compared under
+    /// `policy`. Contacts are returned sorted by last name
+    pub fn find_query(&self, query: &Query, policy: MatchPolicy) -> Result<Vec<&Contact>> {
+        let ids = self.query_ids(query, policy)?;
+
+        Ok(self.contacts_by_last_name(ids.into_iter()))
+    }
+
+    fn query_ids(&self, query: &Query, policy: MatchPolicy) -> Result<HashSet<&ContactId>> {
+        Ok(match query {
+            Query::Term(term) => {
+                let mut ids = HashSet::new();
+                for field in &term.fields {
+                    ids.extend(self.term_ids(term, *field, policy)?);
+                }
+                ids
+            }
+            Query::Not(query) => {
+                let excluded = self.query_ids(query, policy)?;
+                self.contacts
+                    .keys()
+                    .filter(|id| !excluded.contains(id))
+                    .collect()
+            }
+            Query::And(a, b) => {
+                let a = self.query_ids(a, policy)?;
+                let b = self.query_ids(b, policy)?;
+                a.intersection(&b).copied().collect()
+            }
+            Query::Or(a, b) => {
+                let mut a = self.query_ids(a, policy)?;
+                a.extend(self.query_ids(b, policy)?);
+                a
+            }
+        })
+    }
+
+    /// Ids of the contacts with `field` matching the term. Phone numbers are compared by their
+    /// digits, a value that isn't a phone number is an error when the term only searches the
+    /// phone number and matches no phone numbers when it searches other fields as well
+    fn term_ids(
+        &self,
+        term: &Term,
+        field: ContactField,
+        policy: MatchPolicy,
+    ) -> Result<Vec<&ContactId>> {
+        if field != ContactField::PhoneNumber {
+            return Ok(match term.prefix {
+                true => self
+                    .field_index
+                    .get_prefix(field, &term.value, policy)
+                    .collect(),
+                false => self.field_index.get(field, &term.value, policy).collect(),
+            });
+        }
+
+        let only_phone = term.fields == [ContactField::PhoneNumber];
+        let ids = match term.prefix {
+            true => phone_number::normalize_prefix(&term.value, self.region)
+                .map(|prefix| self.phone_index.prefix(&prefix)),
+            false => PhoneNumber::parse(&term.value, self.region)
+                .map(|number| self.phone_index.get(number.digits()).into_iter().collect()),
+        };
+
+        match ids {
+            Ok(ids) => Ok(ids),
+            Err(error) if only_phone => Err(error),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Looks up every contact with a first name, last name or full name starting with the letters
+    /// typed as `digits` on a phone keypad, where 0 is a space. Contacts are returned sorted by
+    /// last name
+    pub fn find_t9(&self, digits: &str) -> Result<Vec<&Contact>> {
+        let ids = self.t9_index.prefix(digits)?;
+
+        Ok(self.contacts_by_last_name(ids.into_iter()))
+    }
+
+    /// Looks up every contact whose phone number starts with the given prefix. Prefixes starting
+    /// with `+` are matched against the international number, anything else is treated as the
+    /// start of a national number in the phone book's region
+    pub fn find_prefix(&self, prefix: &str) -> Result<Vec<&Contact>> {
+        let prefix = phone_number::normalize_prefix(prefix, self.region)?;
+
+        // a contact with several numbers sharing the prefix should only be returned once
+        let mut seen = HashSet::new();
+        let ids = self
+            .phone_index
+            .prefix(&prefix)
+            .into_iter()
+            .filter(|id| seen.insert(*id));
+
+        Ok(self.contacts_for(ids))
+    }
+
+    fn index_contact(&mut self, contact: &Contact) -> Result<()> {
+        for number in contact.numbers() {
+            self.phone_index
+                .insert(number.digits(), contact.id)
+                .map_err(|_| PhoneBookError::InvalidPhoneNumber {
+                    number: number.to_string(),
+                    reason: "phone number must only contain digits",
+                })?;
+        }
+        self.field_index.insert(contact);
+        self.t9_index.insert(contact);
+
+        Ok(())
+    }
+
+    fn unindex_contact(&mut self, contact: &Contact) {
+        for number in contact.numbers() {
+            self.phone_index.remove(number.digits());
+        }
+        self.field_index.remove(contact);
+        self.t9_index.remove(contact);
+    }
+
+    fn contacts_for<'a>(&'a self, ids: impl Iterator<Item = &'a ContactId>) -> Vec<&'a Contact> {
+        ids.filter_map(|id| self.contacts.get(id))
             .collect::<Vec<_>>()
     }
+
+    /// The contacts with the given ids ordered like [`PhoneBook::iter_sorted`] by last name,
+    /// without sorting the rest of the phone book
+    fn contacts_by_last_name<'a>(
+        &'a self,
+        ids: impl Iterator<Item = &'a ContactId>,
+    ) -> Vec<&'a Contact> {
+        let mut contacts = self.contacts_for(ids);
+        contacts.sort_by_cached_key(|c| (SortKey::LastName.key(c), c.id));
+
+        contacts
+    }
 }
 
-fn is_valid_phone_number(number: &str) -> Result<()> {
-    if number.len() == 10 {
-        return Ok(());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(s: &str) -> Address {
+        s.parse().unwrap()
+    }
+
+    fn parts(address: &Address) -> [&str; 5] {
+        address.fields().map(|(_, value)| value)
+    }
+
+    /// The reason parsing `s` as an address failed
+    fn reason(s: &str) -> &'static str {
+        match s.parse::<Address>() {
+            Err(PhoneBookError::InvalidAddress { reason, .. }) => reason,
+            other => panic!("expected {:?} to be invalid, got {:?}", s, other),
+        }
+    }
+
+    #[test]
+    fn address_parts_are_trimmed_and_collapsed() {
+        let address = address("  12  Smith St ,Melbourne,  VIC , 3000,Australia ");
+        assert_eq!(
+            parts(&address),
+            ["12 Smith St", "Melbourne", "VIC", "3000", "Australia"]
+        );
     }
 
-    Err(anyhow!("phone number must be 10 characters long"))
+    #[test]
+    fn quoted_and_escaped_commas_stay_in_their_part() {
+        let quoted = address(r#""Unit 4, 12 Smith St", Melbourne, VIC, 3000, Australia"#);
+        assert_eq!(quoted.street_address, "Unit 4, 12 Smith St");
+        assert_eq!(quoted.city, "Melbourne");
+
+        let escaped = address(r"Unit 4\, 12 Smith St, Melbourne, VIC, 3000, Australia");
+        assert_eq!(escaped.street_address, "Unit 4, 12 Smith St");
+        assert_eq!(escaped.country, "Australia");
+
+        let escaped_quote = address(r#"The \"Old\" Mill, Perth, WA, 6000, Australia"#);
+        assert_eq!(escaped_quote.street_address, r#"The "Old" Mill"#);
+    }
+
+    #[test]
+    fn extra_leading_parts_belong_to_the_street_address() {
+        let address = address("Unit 4, 12 Smith St, Melbourne, VIC, 3000, Australia");
+        assert_eq!(
+            parts(&address),
+            [
+                "Unit 4, 12 Smith St",
+                "Melbourne",
+                "VIC",
+                "3000",
+                "Australia"
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_addresses_leave_the_missing_parts_empty() {
+        assert_eq!(
+            parts(&address("12 Smith St, Melbourne")),
+            ["12 Smith St", "Melbourne", "", "", ""]
+        );
+        assert_eq!(
+            parts(&address(", Melbourne, , , Australia")),
+            ["", "Melbourne", "", "", "Australia"]
+        );
+    }
+
+    #[test]
+    fn malformed_addresses_are_rejected() {
+        assert_eq!(
+            reason(r#""Unit 4, 12 Smith St, Melbourne"#),
+            "address has an unclosed quote"
+        );
+        assert_eq!(
+            reason(r"12 Smith St, Melbourne\"),
+            "address ends with an unfinished escape"
+        );
+        assert_eq!(reason(" , ,  "), "address is empty");
+    }
+
+    #[test]
+    fn replaced_and_deleted_contacts_leave_nothing_in_the_indexes() {
+        let number = |s: &str| s.parse::<PhoneNumber>().unwrap();
+        let mut phone_book = PhoneBook::new();
+        let contact = Contact::new(
+            "John".to_string(),
+            "Smith".to_string(),
+            vec![LabelledNumber {
+                label: PhoneLabel::Mobile,
+                number: number("5551234567"),
+                primary: true,
+            }],
+            Some(address("12 Smith St, Perth, WA, 6000, Australia")),
+        );
+        let id = contact.id;
+        phone_book.insert_contact(contact.clone()).unwrap();
+
+        let mut replacement = contact;
+        replacement.last_name = "Jones".to_string();
+        replacement.address = Some(address("1 Main St, Sydney, NSW, 2000, Australia"));
+        replacement.phone_numbers[0].number = number("5559876543");
+        phone_book.replace_contact(replacement).unwrap();
+
+        assert!(phone_book.find_any("Smith").is_empty());
+        assert!(phone_book.find_city("Perth".to_string()).is_empty());
+        assert!(phone_book.find_prefix("555123").unwrap().is_empty());
+        assert!(phone_book.find_t9("76484").unwrap().is_empty());
+        assert_eq!(phone_book.find_any("Jones")[0].contact.id, id);
+
+        phone_book.delete_contact(number("5559876543")).unwrap();
+        assert!(phone_book.contacts.is_empty());
+        assert!(phone_book.phone_index.is_empty());
+        assert!(phone_book.field_index.is_empty());
+        assert!(phone_book.t9_index.is_empty());
+    }
+
+    #[test]
+    fn changing_a_number_to_itself_does_nothing() {
+        let number = |s: &str| s.parse::<PhoneNumber>().unwrap();
+        let mut phone_book = PhoneBook::new();
+        let contact = Contact::new(
+            "John".to_string(),
+            "Smith".to_string(),
+            vec![LabelledNumber {
+                label: PhoneLabel::Mobile,
+                number: number("5551234567"),
+                primary: true,
+            }],
+            None,
+        );
+        phone_book.insert_contact(contact).unwrap();
+
+        phone_book
+            .change_phone_number(number("5551234567"), number("(555) 123-4567"))
+            .unwrap();
+        assert!(phone_book.find_phone_number(number("5551234567")).is_ok());
+
+        assert!(matches!(
+            phone_book.change_phone_number(number("5550000000"), number("5550000000")),
+            Err(PhoneBookError::ContactNotFound(_))
+        ));
+    }
 }
@@ -0,0 +1,87 @@
+use crate::{atomic, PhoneBookError, Result};
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a blocked lock is retried while waiting for it
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    /// Any number of readers can hold a shared lock at once
+    Shared,
+    /// Only one writer can hold an exclusive lock and no readers can hold a shared lock with it
+    Exclusive,
+}
+
+/// An advisory lock on a phone book file, held until the value is dropped. The lock is taken on
+/// a `.lock` file next to the phone book rather than the phone book itself, as saving replaces
+/// the phone book file and any lock held on the old file would no longer protect the new one.
+///
+/// Only exclusive locks create the `.lock` file. Without one no writer has ever locked the phone
+/// book, so a shared lock has nothing to wait for and holds no file, which keeps reads from
+/// leaving a `.lock` file behind or failing in a directory they can't write to
+#[derive(Debug)]
+pub struct FileLock {
+    file: Option<File>,
+}
+
+impl FileLock {
+    /// Takes the lock for the phone book at `path`, waiting up to `timeout` for other processes
+    /// to release it
+    pub fn acquire(path: &OsStr, mode: LockMode, timeout: Duration) -> Result<FileLock> {
+        let lock_path = lock_path(Path::new(path));
+        let file = match mode {
+            LockMode::Shared => match File::open(&lock_path) {
+                Ok(file) => file,
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    return Ok(FileLock { file: None })
+                }
+                Err(error) => return Err(error.into()),
+            },
+            LockMode::Exclusive => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&lock_path)?,
+        };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let result = match mode {
+                LockMode::Shared => file.try_lock_shared(),
+                LockMode::Exclusive => file.try_lock(),
+            };
+
+            match result {
+                Ok(()) => return Ok(FileLock { file: Some(file) }),
+                Err(TryLockError::Error(error)) => return Err(error.into()),
+                Err(TryLockError::WouldBlock) if Instant::now() >= deadline => {
+                    return Err(PhoneBookError::LockTimeout {
+                        path: lock_path,
+                        timeout,
+                    })
+                }
+                Err(TryLockError::WouldBlock) => thread::sleep(RETRY_INTERVAL),
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // closing the file would release the lock anyway, unlocking explicitly just makes it
+        // happen without waiting on the handle to be closed
+        if let Some(file) = &self.file {
+            let _ = file.unlock();
+        }
+    }
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    atomic::suffixed_path(path, ".lock")
+}
@@ -1,25 +1,63 @@
-use anyhow::{anyhow, Result};
-use clap::{Args, Parser, Subcommand};
+use anyhow::{anyhow, Context, Result};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use phone_book::csv::{Column, ColumnMapping};
+use phone_book::format::OutputFormat;
+use phone_book::storage::{Backend, StorageLocation};
+use phone_book::vcard::VCardVersion;
 use phone_book::*;
-use std::ffi::OsStr;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
+
+/// Environment variable holding the two letter code of the region used for numbers entered
+/// without a country calling code, defaults to US
+const REGION_VARIABLE: &str = "PHONE_BOOK_REGION";
 
 /// Simple phone book manager
+///
+/// Phone numbers without a leading + are read as national numbers of the region set in the
+/// PHONE_BOOK_REGION environment variable (US if unset)
+///
+/// Exits with status 0 on success, 1 when a search or list finds nothing, 2 for invalid arguments
+/// and from 3 when a command fails, with a distinct status for each kind of failure up to 100 for
+/// failures that aren't any of the known kinds
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about)]
 struct Arguments {
     #[clap(subcommand)]
     command: Commands,
     #[clap(short = 'f', default_value_t = String::from("phone_book.json"), value_parser, value_hint = clap::ValueHint::DirPath)]
-    /// File to save and load the json from
+    /// File to save and load the phone book from, prefix with sqlite: to use a SQLite database
+    /// (when built with the sqlite feature) or journal: to append changes to a journal next to
+    /// the file
     file: String,
+    /// Storage backend for files without a json:, journal: or sqlite: prefix
+    #[clap(long, value_parser)]
+    backend: Option<Backend>,
+    /// Keep the previous version of the file with a .bak extension when saving
+    #[clap(long)]
+    backup: bool,
+    /// Format for search results, table, json, jsonl, csv or pretty
+    #[clap(short = 'o', long, default_value = "table", value_parser = parse_output_format)]
+    output: OutputFormat,
+    /// Seconds to wait for other commands using the file to finish
+    #[clap(long, default_value_t = 10, value_parser)]
+    lock_timeout: u64,
+    /// Show everything that led to an error rather than just the first and last cause
+    #[clap(short = 'v', long)]
+    verbose: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Creates json file (this file needs to exist before other commands can be run)
+    /// Creates the phone book file (this file needs to exist before other commands can be run)
     Init {},
-    /// Add a new contact into the json file
+    /// Add a new contact into the phone book
     Add {
         #[clap(value_parser)]
         /// first name
@@ -27,31 +65,140 @@ enum Commands {
         #[clap(value_parser)]
         /// last name
         last: String,
-        /// phone number (must be exactly ten digits)
-        phone_number: String,
-        #[clap(value_parser = parse_address)]
+        /// phone number (include a leading + and country code for international numbers)
+        #[clap(value_parser = parse_phone_number)]
+        phone_number: PhoneNumber,
+        /// address as street, city, state, postcode, country with any part left empty, quote
+        /// parts or escape commas with a backslash to include a comma
+        #[clap(value_parser)]
         address: Option<Address>,
+        #[clap(flatten)]
+        address_parts: AddressParts,
+        /// label for the phone number (mobile, home, work, fax or anything else)
+        #[clap(long, default_value = "mobile", value_parser)]
+        label: PhoneLabel,
+        /// additional phone number in the form label:number, can be given more than once
+        #[clap(short = 'n', long = "number", value_parser = parse_labelled_number)]
+        numbers: Vec<(PhoneLabel, PhoneNumber)>,
     },
     /// Modify an existing contact
     Update {
-        /// phone number (must be exactly ten digits)
-        #[clap(value_parser)]
-        phone_number: String,
+        /// phone number (include a leading + and country code for international numbers)
+        #[clap(value_parser = parse_phone_number)]
+        phone_number: PhoneNumber,
         #[clap(short = 'f', value_parser)]
         /// first name
         first: Option<String>,
         #[clap(short = 'l', value_parser)]
         /// last name
         last: Option<String>,
-        #[clap(short = 'a', value_parser = parse_address)]
+        /// replaces the address, in the same form as for add
+        #[clap(short = 'a', value_parser)]
         address: Option<Address>,
+        /// removes the address, address parts given with this start a new address
+        #[clap(long, conflicts_with = "address")]
+        clear_address: bool,
+        #[clap(flatten)]
+        address_parts: AddressParts,
+        /// phone number to add in the form label:number, can be given more than once
+        #[clap(long, value_parser = parse_labelled_number)]
+        add_number: Vec<(PhoneLabel, PhoneNumber)>,
+        /// phone number to remove from the contact, can be given more than once
+        #[clap(long, value_parser = parse_phone_number)]
+        remove_number: Vec<PhoneNumber>,
+        /// replaces the phone number used to find the contact, keeping its label
+        #[clap(long, value_parser = parse_phone_number)]
+        new_phone: Option<PhoneNumber>,
+        /// makes this phone number the primary one, it can be one of the numbers added
+        #[clap(long, value_parser = parse_phone_number)]
+        primary: Option<PhoneNumber>,
     },
     Delete {
-        /// phone number (must be exactly ten digits)
-        #[clap(value_parser)]
-        phone_number: String,
+        /// phone number (include a leading + and country code for international numbers)
+        #[clap(value_parser = parse_phone_number)]
+        phone_number: PhoneNumber,
     },
     Search(Search),
+    /// List every contact
+    List {
+        /// sort by last-name, first-name, city or phone, names are compared ignoring case and
+        /// accents in the same order for every language, so "Åsa" sorts with "Asa"
+        #[clap(long, default_value = "last-name", value_parser = parse_sort_key)]
+        sort: SortKey,
+        /// sort in descending order
+        #[clap(long)]
+        descending: bool,
+        /// show at most this many contacts
+        #[clap(long, value_parser)]
+        limit: Option<usize>,
+        /// skip this many contacts before showing any
+        #[clap(long, default_value_t = 0, value_parser)]
+        offset: usize,
+        /// only list contacts with this first name
+        #[clap(long, value_parser)]
+        first: Option<String>,
+        /// only list contacts with this last name
+        #[clap(long, value_parser)]
+        last: Option<String>,
+        /// only list contacts in this city
+        #[clap(long, value_parser)]
+        city: Option<String>,
+        /// only list contacts matching a query, in the same form as for search query
+        #[clap(long, value_parser)]
+        query: Option<String>,
+        /// how the filters compare names and address fields, exact, case-insensitive or
+        /// normalized
+        #[clap(long = "match", default_value = "exact", value_parser = parse_match_policy)]
+        policy: MatchPolicy,
+    },
+    /// Add contacts from a file in another format
+    Import(Import),
+    /// Write every contact to a file in another format
+    Export(Export),
+    /// Folds the journal of a journal: phone book into a fresh snapshot
+    Compact {},
+}
+
+/// Address parts given separately, each one replaces the same part of an address given as a
+/// single string or of the contact's existing address. An empty value clears the part
+#[derive(Args)]
+struct AddressParts {
+    /// street address
+    #[clap(long, value_parser = parse_address_part)]
+    street: Option<String>,
+    #[clap(long, value_parser = parse_address_part)]
+    city: Option<String>,
+    /// state or province
+    #[clap(long, value_parser = parse_address_part)]
+    state: Option<String>,
+    /// postcode or zip code
+    #[clap(long, value_parser = parse_address_part)]
+    postcode: Option<String>,
+    #[clap(long, value_parser = parse_address_part)]
+    country: Option<String>,
+}
+
+impl AddressParts {
+    /// A patch making only the changes to the address parts
+    fn patch(self) -> ContactPatch {
+        ContactPatch {
+            street_address: change(self.street),
+            city: change(self.city),
+            state: change(self.state),
+            postcode: change(self.postcode),
+            country: change(self.country),
+            ..ContactPatch::default()
+        }
+    }
+}
+
+/// A value given on the command line as a change, an empty value clears the field
+fn change(value: Option<String>) -> Change<String> {
+    match value {
+        None => Change::Keep,
+        Some(value) if value.is_empty() => Change::Clear,
+        Some(value) => Change::Set(value),
+    }
 }
 
 #[derive(Args)]
@@ -64,10 +211,12 @@ struct Search {
 enum SearchCommands {
     /// search for a contact using phone number
     Phone {
-        /// phone number (must be exactly ten digits)
-        phone_number: String,
+        /// phone number (include a leading + and country code for international numbers)
+        #[clap(value_parser = parse_phone_number)]
+        phone_number: PhoneNumber,
     },
-    /// search for a contact using first and last names
+    /// search for a contact using first and last names, contacts have to match every name given
+    #[clap(group(ArgGroup::new("names").required(true).multiple(true).args(&["first", "last", "either"])))]
     Name {
         /// first name
         #[clap(short = 'f', value_parser)]
@@ -75,14 +224,74 @@ enum SearchCommands {
         /// last name
         #[clap(short = 'l', value_parser)]
         last: Option<String>,
+        /// name matching either the first or the last name
+        #[clap(
+            short = 'n',
+            long = "name",
+            value_name = "NAME",
+            conflicts_with_all = &["first", "last"],
+            value_parser
+        )]
+        either: Option<String>,
+        /// how much of a name has to match, exact, prefix or contains
+        #[clap(long, default_value = "exact", value_parser = parse_name_match)]
+        mode: NameMatch,
+        /// how names are compared, exact, case-insensitive or normalized
+        #[clap(long = "match", default_value = "exact", value_parser = parse_match_policy)]
+        policy: MatchPolicy,
+    },
+    /// search for contacts with names that sound like the given ones, closest spelling first
+    #[clap(group(ArgGroup::new("names").required(true).multiple(true).args(&["first", "last"])))]
+    SoundsLike {
+        /// first name
+        #[clap(short = 'f', value_parser)]
+        first: Option<String>,
+        /// last name
+        #[clap(short = 'l', value_parser)]
+        last: Option<String>,
     },
     City {
         #[clap(value_parser)]
         city: String,
+        /// how the city is compared, exact, case-insensitive or normalized
+        #[clap(long = "match", default_value = "exact", value_parser = parse_match_policy)]
+        policy: MatchPolicy,
     },
+    /// search for contacts with any field matching the given value
     Fuzzy {
         #[clap(value_parser)]
         search: String,
+        /// how fields other than the phone number are compared, exact, case-insensitive or
+        /// normalized (ignoring case, accents and extra spaces)
+        #[clap(long = "match", default_value = "exact", value_parser = parse_match_policy)]
+        policy: MatchPolicy,
+        /// rank contacts with a name, city or street address within a few typos of the search
+        #[clap(long, conflicts_with = "policy")]
+        fuzzy: bool,
+        /// most typos a value can have and still match a fuzzy search
+        #[clap(long, default_value_t = 2, requires = "fuzzy", value_parser)]
+        max_distance: usize,
+        /// only show this many of the best fuzzy matches
+        #[clap(long, requires = "fuzzy", value_parser)]
+        limit: Option<usize>,
+    },
+    /// search with a query combining terms such as `last:smith AND city:melbourne AND NOT
+    /// phone:04*`. A term without a field searches every field, `name:` searches the first and
+    /// last names and a value ending in * matches any value starting with it. Terms can be
+    /// combined with AND, OR, NOT and parentheses, terms without an operator between them must
+    /// all match
+    Query {
+        #[clap(value_parser)]
+        query: String,
+        /// how names and address fields are compared, exact, case-insensitive or normalized
+        #[clap(long = "match", default_value = "exact", value_parser = parse_match_policy)]
+        policy: MatchPolicy,
+    },
+    /// search for contacts with a first, last or full name starting with letters typed on a phone
+    /// keypad, 2 for abc up to 9 for wxyz and 0 for the space between names
+    T9 {
+        #[clap(value_parser)]
+        digits: String,
     },
     /// returns contacts for which the given prefix matches the beginning of a contact's phone number
     Prefix {
@@ -91,32 +300,410 @@ enum SearchCommands {
     },
 }
 
-/// `[street address], [city], [state/province], [zip code], [country]`
-fn parse_address(s: &str) -> Result<Address> {
-    let split: Vec<&str> = s.split(',').collect();
+#[derive(Args)]
+struct Import {
+    #[clap(subcommand)]
+    command: ImportCommands,
+}
+
+#[derive(Subcommand)]
+enum ImportCommands {
+    /// import contacts from a vCard file, cards with invalid or existing numbers are skipped
+    Vcard {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    /// import contacts from a CSV file, rows with invalid or existing numbers are skipped
+    Csv {
+        #[clap(value_parser)]
+        file: PathBuf,
+        #[clap(flatten)]
+        layout: CsvLayout,
+        /// report which rows would be skipped without importing anything
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Args)]
+struct Export {
+    #[clap(subcommand)]
+    command: ExportCommands,
+}
 
-    if split.len() != 5 {
-        return Err(anyhow!("address must have five comma separated values"));
+#[derive(Subcommand)]
+enum ExportCommands {
+    /// export every contact to a vCard file
+    Vcard {
+        #[clap(value_parser)]
+        file: PathBuf,
+        /// vCard version to write, 3.0 or 4.0
+        #[clap(long = "vcard-version", default_value = "4.0", value_parser = parse_vcard_version)]
+        version: VCardVersion,
+    },
+    /// export every contact to a CSV file
+    Csv {
+        #[clap(value_parser)]
+        file: PathBuf,
+        #[clap(flatten)]
+        layout: CsvLayout,
+    },
+}
+
+#[derive(Args)]
+struct CsvLayout {
+    /// column layout, native, google or outlook
+    #[clap(long, default_value = "native", value_parser = parse_preset)]
+    preset: ColumnMapping,
+    /// column in the form header=field replacing the preset, can be given more than once. Fields
+    /// are first_name, last_name, primary_phone, phone:<label>, phone_value:<n>, phone_type:<n>,
+    /// street_address, city, state, postcode and country
+    #[clap(long, value_parser = parse_column)]
+    map: Vec<(String, Column)>,
+}
+
+impl CsvLayout {
+    fn mapping(self) -> ColumnMapping {
+        match self.map.is_empty() {
+            true => self.preset,
+            false => ColumnMapping { columns: self.map },
+        }
+    }
+}
+
+/// Trims and collapses whitespace the same way as parts of an address given as one string
+fn parse_address_part(s: &str) -> Result<String> {
+    Ok(s.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// `[label]:[phone number]`
+fn parse_labelled_number(s: &str) -> Result<(PhoneLabel, PhoneNumber)> {
+    let (label, number) = s
+        .split_once(':')
+        .ok_or_else(|| anyhow!("phone number must be given as label:number"))?;
+
+    Ok((label.trim().parse()?, parse_phone_number(number)?))
+}
+
+/// Reads numbers without a country calling code as numbers of the region in REGION_VARIABLE
+fn parse_phone_number(s: &str) -> Result<PhoneNumber> {
+    Ok(PhoneNumber::parse(s, input_region()?)?)
+}
+
+fn input_region() -> Result<&'static Region> {
+    match env::var(REGION_VARIABLE) {
+        Ok(code) => Region::find(&code).with_context(|| format!("{} is invalid", REGION_VARIABLE)),
+        Err(_) => Ok(Region::default_region()),
+    }
+}
+
+fn parse_vcard_version(s: &str) -> Result<VCardVersion> {
+    match s {
+        "3" | "3.0" => Ok(VCardVersion::V3),
+        "4" | "4.0" => Ok(VCardVersion::V4),
+        _ => Err(anyhow!("vCard version must be 3.0 or 4.0")),
     }
+}
+
+fn parse_sort_key(s: &str) -> Result<SortKey> {
+    match s.to_lowercase().replace('_', "-").as_str() {
+        "last-name" => Ok(SortKey::LastName),
+        "first-name" => Ok(SortKey::FirstName),
+        "city" => Ok(SortKey::City),
+        "phone" => Ok(SortKey::Phone),
+        _ => Err(anyhow!("sort must be last-name, first-name, city or phone")),
+    }
+}
+
+fn parse_match_policy(s: &str) -> Result<MatchPolicy> {
+    match s.to_lowercase().replace('_', "-").as_str() {
+        "exact" => Ok(MatchPolicy::Exact),
+        "case-insensitive" => Ok(MatchPolicy::CaseInsensitive),
+        "normalized" => Ok(MatchPolicy::Normalized),
+        _ => Err(anyhow!(
+            "match must be exact, case-insensitive or normalized"
+        )),
+    }
+}
+
+fn parse_name_match(s: &str) -> Result<NameMatch> {
+    match s.to_lowercase().as_str() {
+        "exact" => Ok(NameMatch::Exact),
+        "prefix" => Ok(NameMatch::Prefix),
+        "contains" => Ok(NameMatch::Contains),
+        _ => Err(anyhow!("mode must be exact, prefix or contains")),
+    }
+}
+
+fn parse_output_format(s: &str) -> Result<OutputFormat> {
+    match s.to_lowercase().as_str() {
+        "table" => Ok(OutputFormat::Table),
+        "json" => Ok(OutputFormat::Json),
+        "jsonl" => Ok(OutputFormat::JsonLines),
+        "csv" => Ok(OutputFormat::Csv),
+        "pretty" => Ok(OutputFormat::Pretty),
+        _ => Err(anyhow!("output must be table, json, jsonl, csv or pretty")),
+    }
+}
+
+fn parse_preset(s: &str) -> Result<ColumnMapping> {
+    match s.to_lowercase().as_str() {
+        "native" => Ok(ColumnMapping::native()),
+        "google" => Ok(ColumnMapping::google()),
+        "outlook" => Ok(ColumnMapping::outlook()),
+        _ => Err(anyhow!("preset must be native, google or outlook")),
+    }
+}
+
+/// `[header]=[field]`
+fn parse_column(s: &str) -> Result<(String, Column)> {
+    let (header, field) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow!("column must be given as header=field"))?;
+
+    let position = |n: &str| -> Result<usize> {
+        match n.parse() {
+            Ok(0) | Err(_) => Err(anyhow!("phone positions count from 1")),
+            Ok(n) => Ok(n),
+        }
+    };
+
+    let column = match field.trim().split_once(':') {
+        Some(("phone", label)) => Column::Phone(label.parse()?),
+        Some(("phone_value", n)) => Column::PhoneValue(position(n)?),
+        Some(("phone_type", n)) => Column::PhoneType(position(n)?),
+        None => match field.trim() {
+            "first_name" => Column::FirstName,
+            "last_name" => Column::LastName,
+            "primary_phone" => Column::PrimaryPhone,
+            "street_address" => Column::StreetAddress,
+            "city" => Column::City,
+            "state" => Column::State,
+            "postcode" => Column::Postcode,
+            "country" => Column::Country,
+            _ => return Err(anyhow!("unknown field {:?}", field)),
+        },
+        Some(_) => return Err(anyhow!("unknown field {:?}", field)),
+    };
 
-    // TODO this is bad, index operation can panic
-    Ok(Address {
-        street_address: split[0].trim().to_string(),
-        city: split[1].trim().to_string(),
-        state: split[2].trim().to_string(),
-        postcode: split[3].trim().to_string(),
-        country: split[4].trim().to_string(),
+    Ok((header.to_string(), column))
+}
+
+/// Exit status of a search that found nothing, kept apart from errors so scripts can tell an
+/// empty result from a failure
+const NOT_FOUND: i32 = 1;
+
+/// Exit status of a failure that is neither a phone book error nor an io error. It is kept clear
+/// of the statuses for phone book errors so new kinds of error can be numbered after them
+const FAILED: i32 = 100;
+
+/// How a command that didn't fail finished
+enum Outcome {
+    Done,
+    NotFound,
+}
+
+/// Process exit status for each kind of phone book error, 1 is [`NOT_FOUND`] and 2 is left for
+/// the usage errors reported by clap
+fn exit_code(error: &PhoneBookError) -> i32 {
+    match error {
+        PhoneBookError::InvalidPhoneNumber { .. } => 3,
+        PhoneBookError::InvalidPrefix { .. } => 4,
+        PhoneBookError::DuplicatePhoneNumber(_) => 5,
+        PhoneBookError::ContactNotFound(_) => 6,
+        PhoneBookError::Io(_) => 7,
+        PhoneBookError::Parse { .. } => 8,
+        PhoneBookError::UnknownRegion(_) => 9,
+        PhoneBookError::InvalidContact { .. } => 10,
+        PhoneBookError::InvalidContactId(_) => 11,
+        PhoneBookError::DuplicateContactId(_) => 12,
+        PhoneBookError::ContactIdNotFound(_) => 13,
+        PhoneBookError::LockTimeout { .. } => 14,
+        PhoneBookError::UnsupportedStorage(_) => 15,
+        PhoneBookError::Storage(_) => 16,
+        PhoneBookError::InvalidVCard { .. } => 17,
+        PhoneBookError::InvalidCsv { .. } => 18,
+        PhoneBookError::InvalidAddress { .. } => 19,
+        PhoneBookError::NumberNotOnContact(_) => 20,
+        PhoneBookError::InvalidQuery { .. } => 21,
+        PhoneBookError::InvalidNameQuery { .. } => 22,
+        PhoneBookError::InvalidKeypadDigits { .. } => 23,
+    }
+}
+
+/// The query a contact has to match to be listed, every filter given has to match
+fn list_filter(
+    first: Option<String>,
+    last: Option<String>,
+    city: Option<String>,
+    query: Option<String>,
+) -> Result<Option<query::Query>> {
+    let fields = [
+        (ContactField::FirstName, first),
+        (ContactField::LastName, last),
+        (ContactField::City, city),
+    ];
+    let mut filters = fields
+        .into_iter()
+        .filter_map(|(field, value)| {
+            Some(query::Query::Term(query::Term {
+                fields: vec![field],
+                value: value?,
+                prefix: false,
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(query) = query {
+        filters.push(query.parse()?);
+    }
+
+    Ok(filters
+        .into_iter()
+        .reduce(|a, b| query::Query::And(Box::new(a), Box::new(b))))
+}
+
+/// Prints the error to stderr and exits with the status for the phone book error that caused it,
+/// 7 for other io errors or [`FAILED`] for anything else. Only the outermost message and the root
+/// cause are shown unless `verbose` is set
+fn fail(error: anyhow::Error, verbose: bool) -> ! {
+    // errors that wrap another error often show the same message, such as io errors
+    let mut causes = error
+        .chain()
+        .map(|cause| cause.to_string())
+        .collect::<Vec<_>>();
+    causes.dedup();
+
+    match causes.as_slice() {
+        [message, rest @ ..] if verbose => {
+            eprintln!("error: {}", message);
+            for cause in rest {
+                eprintln!("  caused by: {}", cause);
+            }
+        }
+        [message, .., root] => eprintln!("error: {}: {}", message, root),
+        [message] => eprintln!("error: {}", message),
+        [] => eprintln!("error: {}", error),
+    }
+
+    let status = error
+        .chain()
+        .find_map(|cause| {
+            if let Some(error) = cause.downcast_ref::<PhoneBookError>() {
+                Some(exit_code(error))
+            } else if cause.is::<io::Error>() {
+                // the status of the library's own io errors
+                Some(7)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(FAILED);
+    exit(status)
+}
+
+fn open_phone_book(location: &StorageLocation, backup: bool) -> Result<PhoneBook> {
+    let storage = location
+        .open(backup)
+        .with_context(|| format!("unable to open phone book {}", location.path.display()))?;
+
+    let mut phone_book = PhoneBook::open(storage)
+        .with_context(|| format!("unable to load phone book {}", location.path.display()))?;
+    phone_book.set_region(input_region()?);
+
+    Ok(phone_book)
+}
+
+fn commit(phone_book: &mut PhoneBook, location: &StorageLocation) -> Result<()> {
+    phone_book
+        .commit()
+        .with_context(|| format!("unable to save phone book {}", location.path.display()))
+}
+
+fn read_file(file: &Path) -> Result<String> {
+    fs::read_to_string(file)
+        .map_err(PhoneBookError::from)
+        .with_context(|| format!("unable to read {}", file.display()))
+}
+
+fn create_file(file: &Path) -> Result<BufWriter<File>> {
+    File::create(file)
+        .map(BufWriter::new)
+        .map_err(PhoneBookError::from)
+        .with_context(|| format!("unable to create {}", file.display()))
+}
+
+/// Prints search results, formats meant for people say so when nothing was found
+fn print_contacts(contacts: &[&Contact], output: OutputFormat) -> Result<Outcome> {
+    if contacts.is_empty() && output.is_human_readable() {
+        println!("didn't find anything");
+    } else {
+        format::write_contacts(io::stdout().lock(), contacts.iter().copied(), output)?;
+    }
+
+    Ok(match contacts.is_empty() {
+        true => Outcome::NotFound,
+        false => Outcome::Done,
     })
 }
 
+fn print_report(report: ImportReport, kind: &str) {
+    for skipped in report.skipped {
+        println!(
+            "Skipped {} at line {} {:?}: {}",
+            kind, skipped.line, skipped.name, skipped.error
+        );
+    }
+}
+
 fn main() {
-    let args = Arguments::parse();
+    let args = match Arguments::try_parse() {
+        Ok(args) => args,
+        // an invalid region would otherwise be reported as an invalid value for the first phone
+        // number argument, help and version are still shown whatever the region
+        Err(error) => match input_region() {
+            Err(region_error) if error.use_stderr() => fail(region_error, false),
+            _ => error.exit(),
+        },
+    };
+    let verbose = args.verbose;
+
+    match run(args) {
+        Ok(Outcome::Done) => {}
+        Ok(Outcome::NotFound) => exit(NOT_FOUND),
+        Err(error) => fail(error, verbose),
+    }
+}
+
+fn run(args: Arguments) -> Result<Outcome> {
+    let location = StorageLocation::parse(&args.file, args.backend);
+
+    // searches can run alongside each other but anything that saves needs the file to itself
+    let lock_mode = match args.command {
+        Commands::Search(_) | Commands::List { .. } | Commands::Export(_) => LockMode::Shared,
+        _ => LockMode::Exclusive,
+    };
+    let _lock = FileLock::acquire(
+        location.path.as_os_str(),
+        lock_mode,
+        Duration::from_secs(args.lock_timeout),
+    )
+    .with_context(|| format!("unable to lock phone book {}", location.path.display()))?;
 
     match args.command {
+        Commands::Compact {} => {
+            let mut phone_book = open_phone_book(&location, args.backup)?;
+            phone_book
+                .compact()
+                .with_context(|| format!("unable to compact {}", location.path.display()))?;
+            println!("Phone book compacted")
+        }
+
         Commands::Init {} => {
-            let new_phone_book = PhoneBook::new();
-            // TODO display a proper error instead of panic on fail
-            new_phone_book.save_to_file(OsStr::new(&args.file)).unwrap();
+            location
+                .create(args.backup)
+                .and_then(|mut storage| storage.commit(&mut std::iter::empty()))
+                .with_context(|| format!("unable to create {}", location.path.display()))?;
             println!("File created")
         }
 
@@ -125,19 +712,31 @@ fn main() {
             last,
             phone_number,
             address,
+            address_parts,
+            label,
+            numbers,
         } => {
-            let mut phone_book = PhoneBook::new_from_file(OsStr::new(&args.file)).unwrap();
+            let mut phone_book = open_phone_book(&location, args.backup)?;
 
+            let mut phone_numbers = vec![LabelledNumber {
+                label,
+                number: phone_number,
+                primary: true,
+            }];
+            phone_numbers.extend(numbers.into_iter().map(|(label, number)| LabelledNumber {
+                label,
+                number,
+                primary: false,
+            }));
+
+            let mut contact = Contact::new(first, last, phone_numbers, address);
+            address_parts.patch().apply(&mut contact)?;
+            let name = format!("{} {}", contact.first_name, contact.last_name);
             phone_book
-                .insert_contact(Contact {
-                    phone_number,
-                    address,
-                    first_name: first,
-                    last_name: last,
-                })
-                .unwrap();
-
-            phone_book.save_to_file(OsStr::new(&args.file)).unwrap();
+                .insert_contact(contact)
+                .with_context(|| format!("unable to add {}", name))?;
+
+            commit(&mut phone_book, &location)?;
             println!("Contact saved")
         }
 
@@ -146,81 +745,229 @@ fn main() {
             last,
             phone_number,
             address,
+            clear_address,
+            address_parts,
+            add_number,
+            remove_number,
+            new_phone,
+            primary,
         } => {
-            let mut phone_book = PhoneBook::new_from_file(OsStr::new(&args.file)).unwrap();
-            let mut existing_contact = phone_book.find_phone_number(phone_number).unwrap().clone();
+            let mut phone_book = open_phone_book(&location, args.backup)?;
+            let id = phone_book
+                .find_phone_number(phone_number.clone())
+                .map(|contact| contact.id)
+                .with_context(|| format!("unable to update contact {}", phone_number))?;
 
-            match first {
-                Some(name) => existing_contact.first_name = name,
-                _ => {}
-            }
-            match last {
-                Some(name) => existing_contact.last_name = name,
-                _ => {}
-            }
-            match address {
-                Some(add) => existing_contact.address = Some(add),
-                _ => {}
-            }
+            let address = match (address, clear_address) {
+                (Some(address), _) => Change::Set(address),
+                (None, true) => Change::Clear,
+                (None, false) => Change::Keep,
+            };
+            let patch = ContactPatch {
+                first_name: change(first),
+                last_name: change(last),
+                address,
+                change_numbers: new_phone
+                    .map(|new_phone| (phone_number.clone(), new_phone))
+                    .into_iter()
+                    .collect(),
+                remove_numbers: remove_number,
+                add_numbers: add_number
+                    .into_iter()
+                    .map(|(label, number)| LabelledNumber {
+                        label,
+                        number,
+                        primary: false,
+                    })
+                    .collect(),
+                primary,
+                ..address_parts.patch()
+            };
 
-            phone_book.replace_contact(existing_contact).unwrap();
-            phone_book.save_to_file(OsStr::new(&args.file)).unwrap();
+            phone_book
+                .patch_contact(id, patch)
+                .with_context(|| format!("unable to update contact {}", phone_number))?;
+            commit(&mut phone_book, &location)?;
             println!("Contact updated")
         }
 
         Commands::Delete { phone_number } => {
-            let mut phone_book = PhoneBook::new_from_file(OsStr::new(&args.file)).unwrap();
-            phone_book.delete_contact(phone_number).unwrap();
-            phone_book.save_to_file(OsStr::new(&args.file)).unwrap();
+            let mut phone_book = open_phone_book(&location, args.backup)?;
+            phone_book.delete_contact(phone_number)?;
+            commit(&mut phone_book, &location)?;
             println!("Contact deleted")
         }
 
-        Commands::Search(search) => match search.command {
-            SearchCommands::Name { first, last } => {
-                if first == None && last == None {
-                    println!("must provide at least one search value");
-                    exit(1)
-                }
+        Commands::Import(import) => match import.command {
+            ImportCommands::Vcard { file } => {
+                let input = read_file(&file)?;
+
+                let mut phone_book = open_phone_book(&location, args.backup)?;
+                let report = phone_book
+                    .import_vcard(&input)
+                    .with_context(|| format!("unable to import {}", file.display()))?;
+                commit(&mut phone_book, &location)?;
 
-                let phone_book = PhoneBook::new_from_file(OsStr::new(&args.file)).unwrap();
+                println!("Imported {} contacts", report.imported);
+                print_report(report, "card");
+            }
 
-                let search_results = phone_book.find_name(first, last);
+            ImportCommands::Csv {
+                file,
+                layout,
+                dry_run,
+            } => {
+                let input = read_file(&file)?;
 
-                if search_results.len() == 0 {
-                    println!("didn't find anything");
-                    exit(0)
-                }
+                let mut phone_book = open_phone_book(&location, args.backup)?;
+                let report = phone_book
+                    .import_csv(&input, &layout.mapping(), dry_run)
+                    .with_context(|| format!("unable to import {}", file.display()))?;
 
-                for result in search_results {
-                    println!("{:?}", result)
+                if dry_run {
+                    println!("Would import {} contacts", report.imported);
+                } else {
+                    commit(&mut phone_book, &location)?;
+                    println!("Imported {} contacts", report.imported);
                 }
+                print_report(report, "row");
             }
-            SearchCommands::Phone { phone_number } => {
-                let phone_book = PhoneBook::new_from_file(OsStr::new(&args.file)).unwrap();
-                match phone_book.find_phone_number(phone_number) {
-                    Ok(result) => println!("{:?}", result),
-                    _ => println!("didn't find anything"),
-                }
+        },
+
+        Commands::Export(export) => match export.command {
+            ExportCommands::Vcard { file, version } => {
+                let phone_book = open_phone_book(&location, args.backup)?;
+                phone_book
+                    .export_vcard(create_file(&file)?, version)
+                    .with_context(|| format!("unable to write {}", file.display()))?;
+                println!("Contacts exported")
             }
-            SearchCommands::City { city } => {
-                let phone_book = PhoneBook::new_from_file(OsStr::new(&args.file)).unwrap();
-                let search_results = phone_book.find_city(city);
 
-                if search_results.len() == 0 {
-                    println!("didn't find anything");
-                    exit(0)
+            ExportCommands::Csv { file, layout } => {
+                let phone_book = open_phone_book(&location, args.backup)?;
+                phone_book
+                    .export_csv(create_file(&file)?, &layout.mapping())
+                    .with_context(|| format!("unable to write {}", file.display()))?;
+                println!("Contacts exported")
+            }
+        },
+
+        Commands::List {
+            sort,
+            descending,
+            limit,
+            offset,
+            first,
+            last,
+            city,
+            query,
+            policy,
+        } => {
+            let phone_book = open_phone_book(&location, args.backup)?;
+
+            let order = match descending {
+                true => SortOrder::Descending,
+                false => SortOrder::Ascending,
+            };
+            let contacts = match list_filter(first, last, city, query)? {
+                Some(filter) => phone_book
+                    .iter_sorted_matching(&filter, policy, sort, order)?
+                    .skip(offset)
+                    .take(limit.unwrap_or(usize::MAX))
+                    .collect::<Vec<_>>(),
+                None => phone_book
+                    .iter_sorted(sort, order)
+                    .skip(offset)
+                    .take(limit.unwrap_or(usize::MAX))
+                    .collect::<Vec<_>>(),
+            };
+
+            return print_contacts(&contacts, args.output);
+        }
+
+        Commands::Search(search) => {
+            let phone_book = open_phone_book(&location, args.backup)?;
+
+            return match search.command {
+                SearchCommands::Name {
+                    first,
+                    last,
+                    either,
+                    mode,
+                    policy,
+                } => {
+                    let target = match (first, last, either) {
+                        (Some(first), Some(last), _) => NameTarget::Both { first, last },
+                        (Some(first), None, _) => NameTarget::First(first),
+                        (None, Some(last), _) => NameTarget::Last(last),
+                        (None, None, name) => NameTarget::Either(name.unwrap_or_default()),
+                    };
+                    let query = NameQuery::new(target, mode, policy)?;
+
+                    print_contacts(&phone_book.find_name(&query), args.output)
                 }
+                SearchCommands::SoundsLike { first, last } => print_contacts(
+                    &phone_book.find_name_phonetic(first.as_deref(), last.as_deref()),
+                    args.output,
+                ),
+                SearchCommands::Phone { phone_number } => {
+                    let search_results = phone_book.find_phone_number(phone_number).ok();
+                    print_contacts(search_results.as_slice(), args.output)
+                }
+                SearchCommands::City { city, policy } => {
+                    print_contacts(&phone_book.find_city_matching(&city, policy), args.output)
+                }
+                SearchCommands::Fuzzy {
+                    search,
+                    fuzzy: true,
+                    max_distance,
+                    limit,
+                    ..
+                } => {
+                    let options = SearchOptions {
+                        max_distance,
+                        limit,
+                    };
+                    let search_results = phone_book.search_ranked(&search, options);
+
+                    if search_results.is_empty() && args.output.is_human_readable() {
+                        println!("didn't find anything");
+                        return Ok(Outcome::NotFound);
+                    }
+                    format::write_ranked(io::stdout().lock(), &search_results, args.output)?;
 
-                for result in search_results {
-                    println!("{:?}", result)
+                    Ok(match search_results.is_empty() {
+                        true => Outcome::NotFound,
+                        false => Outcome::Done,
+                    })
                 }
-            }
-            SearchCommands::Fuzzy { search } => {
-                println!("{:?} unimplemented", search)
-            }
-            SearchCommands::Prefix { search } => {
-                println!("{:?} unimplemented", search)
-            }
-        },
+                SearchCommands::Fuzzy { search, policy, .. } => {
+                    let search_results = phone_book.find_any_matching(&search, policy);
+
+                    if search_results.is_empty() && args.output.is_human_readable() {
+                        println!("didn't find anything");
+                        return Ok(Outcome::NotFound);
+                    }
+                    format::write_matches(io::stdout().lock(), &search_results, args.output)?;
+
+                    Ok(match search_results.is_empty() {
+                        true => Outcome::NotFound,
+                        false => Outcome::Done,
+                    })
+                }
+                SearchCommands::Query { query, policy } => {
+                    let query = query.parse::<query::Query>()?;
+                    print_contacts(&phone_book.find_query(&query, policy)?, args.output)
+                }
+                SearchCommands::T9 { digits } => {
+                    print_contacts(&phone_book.find_t9(&digits)?, args.output)
+                }
+                SearchCommands::Prefix { search } => {
+                    print_contacts(&phone_book.find_prefix(&search)?, args.output)
+                }
+            };
+        }
     }
+
+    Ok(Outcome::Done)
 }
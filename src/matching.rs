@@ -0,0 +1,49 @@
+//! How search values are compared with the values stored on contacts. Every policy turns a value
+//! into a key and two values match when their keys are equal, so the field index can store keys
+//! and answer a search with a single lookup whichever policy it is made with
+
+use crate::collation;
+
+/// How strictly a search value has to agree with a contact's field to match it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MatchPolicy {
+    /// The values must be identical
+    #[default]
+    Exact,
+    /// The values may differ in case, "smith" matches "Smith"
+    CaseInsensitive,
+    /// The values may differ in case, accents, compatibility forms and spacing, "zoe  " matches
+    /// "Zoë" and "ﬁnn" matches "Finn"
+    Normalized,
+}
+
+impl MatchPolicy {
+    pub const ALL: [MatchPolicy; 3] = [
+        MatchPolicy::Exact,
+        MatchPolicy::CaseInsensitive,
+        MatchPolicy::Normalized,
+    ];
+
+    /// The key `value` is looked up by under this policy
+    pub fn key(self, value: &str) -> String {
+        match self {
+            MatchPolicy::Exact => value.to_string(),
+            MatchPolicy::CaseInsensitive => caseless::default_case_fold_str(value),
+            MatchPolicy::Normalized => normalize(value),
+        }
+    }
+
+    /// Whether `search` matches `value` under this policy
+    pub fn matches(self, search: &str, value: &str) -> bool {
+        self.key(search) == self.key(value)
+    }
+}
+
+/// Replaces compatibility characters with the ones they stand for, drops accents, folds case and
+/// collapses runs of whitespace, see [`collation::fold`]
+fn normalize(value: &str) -> String {
+    collation::fold(value)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,213 @@
+use crate::{MatchPolicy, PhoneBookError, Result};
+
+/// Which of a contact's names a [`NameQuery`] is matched against
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameTarget {
+    First(String),
+    Last(String),
+    /// Both names have to match
+    Both {
+        first: String,
+        last: String,
+    },
+    /// Either the first or the last name has to match
+    Either(String),
+}
+
+/// How much of a name has to match
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NameMatch {
+    /// The whole name
+    #[default]
+    Exact,
+    /// The start of the name
+    Prefix,
+    /// Any part of the name
+    Contains,
+}
+
+/// A search for contacts by name for [`crate::PhoneBook::find_name`], which can only be made with
+/// names that aren't blank
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameQuery {
+    target: NameTarget,
+    mode: NameMatch,
+    policy: MatchPolicy,
+}
+
+impl NameQuery {
+    pub fn new(target: NameTarget, mode: NameMatch, policy: MatchPolicy) -> Result<NameQuery> {
+        let invalid = |reason| PhoneBookError::InvalidNameQuery { reason };
+        let blank = |name: &String| name.trim().is_empty();
+
+        match &target {
+            NameTarget::First(first) if blank(first) => {
+                return Err(invalid("first name must not be empty"))
+            }
+            NameTarget::Last(last) if blank(last) => {
+                return Err(invalid("last name must not be empty"))
+            }
+            NameTarget::Both { first, .. } if blank(first) => {
+                return Err(invalid("first name must not be empty"))
+            }
+            NameTarget::Both { last, .. } if blank(last) => {
+                return Err(invalid("last name must not be empty"))
+            }
+            NameTarget::Either(name) if blank(name) => {
+                return Err(invalid("name must not be empty"))
+            }
+            _ => {}
+        }
+
+        Ok(NameQuery {
+            target,
+            mode,
+            policy,
+        })
+    }
+
+    pub fn target(&self) -> &NameTarget {
+        &self.target
+    }
+
+    pub fn mode(&self) -> NameMatch {
+        self.mode
+    }
+
+    pub fn policy(&self) -> MatchPolicy {
+        self.policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Contact, LabelledNumber, PhoneBook, PhoneLabel};
+
+    fn phone_book() -> PhoneBook {
+        let mut phone_book = PhoneBook::new();
+        let names = [
+            ("John", "Smith"),
+            ("Johnny", "Smithers"),
+            ("Anne", "Johnson"),
+            // a contact without a last name once matched every first name search
+            ("Jane", ""),
+        ];
+
+        for (index, (first, last)) in names.into_iter().enumerate() {
+            let number = LabelledNumber {
+                label: PhoneLabel::Mobile,
+                number: format!("555000000{}", index).parse().unwrap(),
+                primary: true,
+            };
+            let contact = Contact::new(first.to_string(), last.to_string(), vec![number], None);
+            phone_book.insert_contact(contact).unwrap();
+        }
+
+        phone_book
+    }
+
+    /// The full names of the contacts found, in the order they were returned
+    fn find(target: NameTarget, mode: NameMatch) -> Vec<String> {
+        let query = NameQuery::new(target, mode, MatchPolicy::Exact).unwrap();
+
+        phone_book()
+            .find_name(&query)
+            .into_iter()
+            .map(|contact| format!("{} {}", contact.first_name, contact.last_name))
+            .map(|name| name.trim().to_string())
+            .collect()
+    }
+
+    fn first(name: &str) -> NameTarget {
+        NameTarget::First(name.to_string())
+    }
+
+    fn last(name: &str) -> NameTarget {
+        NameTarget::Last(name.to_string())
+    }
+
+    fn both(first: &str, last: &str) -> NameTarget {
+        NameTarget::Both {
+            first: first.to_string(),
+            last: last.to_string(),
+        }
+    }
+
+    fn either(name: &str) -> NameTarget {
+        NameTarget::Either(name.to_string())
+    }
+
+    #[test]
+    fn first_name_matches_only_first_names() {
+        assert_eq!(find(first("John"), NameMatch::Exact), ["John Smith"]);
+        assert_eq!(
+            find(first("John"), NameMatch::Prefix),
+            ["John Smith", "Johnny Smithers"]
+        );
+        assert_eq!(
+            find(first("ohn"), NameMatch::Contains),
+            ["John Smith", "Johnny Smithers"]
+        );
+    }
+
+    #[test]
+    fn last_name_matches_only_last_names() {
+        assert_eq!(find(last("Smith"), NameMatch::Exact), ["John Smith"]);
+        assert_eq!(
+            find(last("Smith"), NameMatch::Prefix),
+            ["John Smith", "Johnny Smithers"]
+        );
+        assert_eq!(find(last("ohns"), NameMatch::Contains), ["Anne Johnson"]);
+    }
+
+    #[test]
+    fn both_names_have_to_match() {
+        assert_eq!(
+            find(both("John", "Smith"), NameMatch::Exact),
+            ["John Smith"]
+        );
+        assert!(find(both("John", "Smithers"), NameMatch::Exact).is_empty());
+        assert_eq!(
+            find(both("Jo", "Smithe"), NameMatch::Prefix),
+            ["Johnny Smithers"]
+        );
+        assert_eq!(
+            find(both("ohn", "mith"), NameMatch::Contains),
+            ["John Smith", "Johnny Smithers"]
+        );
+    }
+
+    #[test]
+    fn either_name_can_match() {
+        assert_eq!(find(either("Johnson"), NameMatch::Exact), ["Anne Johnson"]);
+        assert_eq!(
+            find(either("John"), NameMatch::Prefix),
+            ["Anne Johnson", "John Smith", "Johnny Smithers"]
+        );
+        assert_eq!(find(either("ane"), NameMatch::Contains), ["Jane"]);
+    }
+
+    #[test]
+    fn empty_last_name_does_not_match_first_name_searches() {
+        for mode in [NameMatch::Exact, NameMatch::Prefix, NameMatch::Contains] {
+            assert!(!find(first("John"), mode).contains(&"Jane".to_string()));
+            assert!(!find(either("John"), mode).contains(&"Jane".to_string()));
+        }
+        assert_eq!(find(first("Jane"), NameMatch::Exact), ["Jane"]);
+    }
+
+    #[test]
+    fn blank_names_are_rejected() {
+        let reason = |target| match NameQuery::new(target, NameMatch::Exact, MatchPolicy::Exact) {
+            Err(PhoneBookError::InvalidNameQuery { reason }) => reason,
+            other => panic!("expected an invalid name query, got {:?}", other),
+        };
+
+        assert_eq!(reason(first("  ")), "first name must not be empty");
+        assert_eq!(reason(last("")), "last name must not be empty");
+        assert_eq!(reason(both("", "Smith")), "first name must not be empty");
+        assert_eq!(reason(both("John", " ")), "last name must not be empty");
+        assert_eq!(reason(either("\t")), "name must not be empty");
+    }
+}
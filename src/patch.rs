@@ -0,0 +1,268 @@
+use crate::{Address, Contact, LabelledNumber, PhoneBookError, PhoneNumber, Result};
+
+/// How a patch changes one field of a contact
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Change<T> {
+    /// Leave the field as it is
+    #[default]
+    Keep,
+    Set(T),
+    /// Empty the field, or remove it for optional fields
+    Clear,
+}
+
+impl Change<String> {
+    fn apply(self, field: &mut String) {
+        match self {
+            Change::Keep => {}
+            Change::Set(value) => *field = value,
+            Change::Clear => field.clear(),
+        }
+    }
+
+    fn is_keep(&self) -> bool {
+        matches!(self, Change::Keep)
+    }
+}
+
+/// Changes to any number of a contact's fields, applied together by
+/// [`crate::PhoneBook::patch_contact`] so either every change is made or none are
+#[derive(Clone, Debug, Default)]
+pub struct ContactPatch {
+    pub first_name: Change<String>,
+    pub last_name: Change<String>,
+    /// Replaces or removes the whole address, applied before the changes to its parts
+    pub address: Change<Address>,
+    /// Changes to parts of the address, setting a part of a contact without an address gives it
+    /// one and clearing every part removes the address
+    pub street_address: Change<String>,
+    pub city: Change<String>,
+    pub state: Change<String>,
+    pub postcode: Change<String>,
+    pub country: Change<String>,
+    /// Replaces each old number with the new one keeping its label and whether it is primary
+    pub change_numbers: Vec<(PhoneNumber, PhoneNumber)>,
+    pub remove_numbers: Vec<PhoneNumber>,
+    pub add_numbers: Vec<LabelledNumber>,
+    /// Makes this number the primary number in place of the current one, it can be one of the
+    /// added numbers
+    pub primary: Option<PhoneNumber>,
+}
+
+impl ContactPatch {
+    /// Makes the changes to `contact`, numbers are changed then removed then added before the
+    /// primary number is chosen. The contact is not validated, a phone book validates it when it
+    /// is stored
+    pub fn apply(self, contact: &mut Contact) -> Result<()> {
+        self.first_name.apply(&mut contact.first_name);
+        self.last_name.apply(&mut contact.last_name);
+
+        match self.address {
+            Change::Keep => {}
+            Change::Set(address) => contact.address = Some(address),
+            Change::Clear => contact.address = None,
+        }
+
+        let parts = [
+            self.street_address,
+            self.city,
+            self.state,
+            self.postcode,
+            self.country,
+        ];
+        if !parts.iter().all(Change::is_keep) {
+            let mut address = contact.address.take().unwrap_or_default();
+            let [street_address, city, state, postcode, country] = parts;
+            street_address.apply(&mut address.street_address);
+            city.apply(&mut address.city);
+            state.apply(&mut address.state);
+            postcode.apply(&mut address.postcode);
+            country.apply(&mut address.country);
+
+            if address.fields().iter().any(|(_, value)| !value.is_empty()) {
+                contact.address = Some(address);
+            }
+        }
+
+        for (old, new) in self.change_numbers {
+            let number = contact
+                .phone_numbers
+                .iter_mut()
+                .find(|number| number.number == old)
+                .ok_or(PhoneBookError::NumberNotOnContact(old))?;
+            number.number = new;
+        }
+
+        for old in self.remove_numbers {
+            let index = contact
+                .phone_numbers
+                .iter()
+                .position(|number| number.number == old)
+                .ok_or(PhoneBookError::NumberNotOnContact(old))?;
+            contact.phone_numbers.remove(index);
+        }
+
+        contact.phone_numbers.extend(self.add_numbers);
+
+        if let Some(primary) = self.primary {
+            if !contact.numbers().any(|number| *number == primary) {
+                return Err(PhoneBookError::NumberNotOnContact(primary));
+            }
+
+            for number in contact.phone_numbers.iter_mut() {
+                number.primary = number.number == primary;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::labelled;
+    use crate::{PhoneBook, PhoneLabel};
+
+    fn number(s: &str) -> PhoneNumber {
+        s.parse().unwrap()
+    }
+
+    fn contact() -> Contact {
+        Contact::new(
+            "John".to_string(),
+            "Smith".to_string(),
+            vec![
+                labelled("5551234567", PhoneLabel::Mobile, true),
+                labelled("5559876543", PhoneLabel::Home, false),
+            ],
+            Some("12 Smith St, Perth, WA, 6000, Australia".parse().unwrap()),
+        )
+    }
+
+    fn numbers(contact: &Contact) -> Vec<(&str, bool)> {
+        contact
+            .phone_numbers
+            .iter()
+            .map(|number| (number.number.as_str(), number.primary))
+            .collect()
+    }
+
+    #[test]
+    fn address_parts_are_changed_in_place() {
+        let mut contact = contact();
+        let patch = ContactPatch {
+            city: Change::Set("Fremantle".to_string()),
+            postcode: Change::Clear,
+            ..ContactPatch::default()
+        };
+        patch.apply(&mut contact).unwrap();
+
+        let address = contact.address.unwrap();
+        assert_eq!(address.street_address, "12 Smith St");
+        assert_eq!(address.city, "Fremantle");
+        assert_eq!(address.postcode, "");
+    }
+
+    #[test]
+    fn clearing_the_last_address_part_removes_the_address() {
+        let mut contact = contact();
+        contact.address = Some(Address {
+            city: "Perth".to_string(),
+            ..Address::default()
+        });
+
+        let patch = ContactPatch {
+            city: Change::Clear,
+            ..ContactPatch::default()
+        };
+        patch.apply(&mut contact).unwrap();
+
+        assert!(contact.address.is_none());
+    }
+
+    #[test]
+    fn numbers_are_changed_then_removed_then_added() {
+        let mut contact = contact();
+        let patch = ContactPatch {
+            // the home number is changed to a new number which is then removed, leaving the old
+            // home number free to be added back as a work number
+            change_numbers: vec![(number("5559876543"), number("5550001111"))],
+            remove_numbers: vec![number("5550001111")],
+            add_numbers: vec![labelled("5559876543", PhoneLabel::Work, false)],
+            ..ContactPatch::default()
+        };
+        patch.apply(&mut contact).unwrap();
+
+        assert_eq!(
+            numbers(&contact),
+            [("+15551234567", true), ("+15559876543", false)]
+        );
+        assert_eq!(contact.phone_numbers[1].label, PhoneLabel::Work);
+    }
+
+    #[test]
+    fn primary_number_can_move_to_an_added_number() {
+        let mut contact = contact();
+        let patch = ContactPatch {
+            remove_numbers: vec![number("5551234567")],
+            add_numbers: vec![labelled("5550001111", PhoneLabel::Work, false)],
+            primary: Some(number("5550001111")),
+            ..ContactPatch::default()
+        };
+        patch.apply(&mut contact).unwrap();
+
+        assert_eq!(
+            numbers(&contact),
+            [("+15559876543", false), ("+15550001111", true)]
+        );
+    }
+
+    #[test]
+    fn primary_number_must_be_on_the_contact() {
+        let patch = ContactPatch {
+            primary: Some(number("5550001111")),
+            ..ContactPatch::default()
+        };
+
+        assert!(matches!(
+            patch.apply(&mut contact()),
+            Err(PhoneBookError::NumberNotOnContact(missing)) if missing == number("5550001111")
+        ));
+    }
+
+    #[test]
+    fn invalid_patch_leaves_the_stored_contact_untouched() {
+        let mut phone_book = PhoneBook::new();
+        let contact = contact();
+        let id = contact.id;
+        phone_book.insert_contact(contact).unwrap();
+
+        // removing the primary number without choosing another leaves no primary number
+        let patch = ContactPatch {
+            first_name: Change::Set("Jack".to_string()),
+            address: Change::Clear,
+            remove_numbers: vec![number("5551234567")],
+            ..ContactPatch::default()
+        };
+        assert!(matches!(
+            phone_book.patch_contact(id, patch),
+            Err(PhoneBookError::InvalidContact { .. })
+        ));
+
+        let stored = phone_book.find_id(id).unwrap();
+        assert_eq!(stored.first_name, "John");
+        assert!(stored.address.is_some());
+        assert_eq!(
+            numbers(stored),
+            [("+15551234567", true), ("+15559876543", false)]
+        );
+        assert_eq!(
+            phone_book
+                .find_phone_number(number("5551234567"))
+                .unwrap()
+                .id,
+            id
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use crate::region::{self, Region};
+use crate::PhoneBookError;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// A validated phone number stored in E.164 form, a `+` followed by the country calling code and
+/// the national significant number. Common punctuation such as spaces, dashes, dots and brackets
+/// is stripped when parsing so `(555) 123-4567` and `5551234567` are the same number
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+    /// Parses a number, numbers starting with `+` are read as international numbers and anything
+    /// else as a national number dialled from `region`. International numbers from countries
+    /// missing from [`crate::REGIONS`] are only checked against the length of any E.164 number
+    pub fn parse(s: &str, region: &'static Region) -> Result<PhoneNumber, PhoneBookError> {
+        let invalid = |reason| PhoneBookError::InvalidPhoneNumber {
+            number: s.to_string(),
+            reason,
+        };
+        let wrong_length =
+            || invalid("phone number has the wrong number of digits for its country");
+
+        let (international, digits) =
+            split_digits(s).ok_or_else(|| invalid("phone number must only contain digits"))?;
+
+        if !international {
+            let national_number = region.strip_trunk_prefix(&digits);
+            if !region.is_valid_length(national_number) {
+                return Err(wrong_length());
+            }
+
+            return Ok(PhoneNumber(format!(
+                "+{}{}",
+                region.calling_code, national_number
+            )));
+        }
+
+        let (calling_code, national_number) = region::split_calling_code(&digits)
+            .ok_or_else(|| invalid("phone number has an unknown country calling code"))?;
+
+        let valid_length = match Region::with_calling_code(calling_code) {
+            Some(region) => region.is_valid_length(national_number),
+            None => {
+                national_number.len() >= region::MIN_NATIONAL_DIGITS
+                    && digits.len() <= region::MAX_DIGITS
+            }
+        };
+        if !valid_length {
+            return Err(wrong_length());
+        }
+
+        Ok(PhoneNumber(format!("+{}", digits)))
+    }
+
+    /// The number in E.164 form
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// The country calling code followed by the national significant number without the `+`
+    pub fn digits(&self) -> &str {
+        &self.0[1..]
+    }
+
+    /// The region the number belongs to, `None` for numbers from countries missing from
+    /// [`crate::REGIONS`]. Countries sharing a calling code resolve to the first one listed
+    pub fn region(&self) -> Option<&'static Region> {
+        Region::with_calling_code(self.split().0)
+    }
+
+    pub fn national_number(&self) -> &str {
+        self.split().1
+    }
+
+    /// The number as it would be dialled from inside its own country, numbers from countries
+    /// missing from [`crate::REGIONS`] are shown without grouping their digits
+    pub fn national(&self) -> String {
+        let (calling_code, national_number) = self.split();
+
+        match Region::with_calling_code(calling_code) {
+            // numbers in the North American plan are written without the trunk prefix, which is
+            // only dialled for long distance calls
+            Some(region) if region.calling_code == 1 => region.group(national_number),
+            Some(region) => format!(
+                "{}{}",
+                region.trunk_prefix.unwrap_or_default(),
+                region.group(national_number)
+            ),
+            None => national_number.to_string(),
+        }
+    }
+
+    /// The number as it would be dialled from another country
+    pub fn international(&self) -> String {
+        let (calling_code, national_number) = self.split();
+
+        match Region::with_calling_code(calling_code) {
+            Some(region) => format!("+{} {}", calling_code, region.group(national_number)),
+            None => format!("+{} {}", calling_code, national_number),
+        }
+    }
+
+    /// The country calling code and national significant number
+    fn split(&self) -> (&str, &str) {
+        // a phone number can only be constructed with a valid calling code
+        region::split_calling_code(self.digits()).expect("phone number has a valid calling code")
+    }
+}
+
+/// Converts the digits of a phone number prefix into the digits of the E.164 numbers it matches,
+/// so `041` entered in Australia becomes `6141`. Prefixes starting with `+` already include the
+/// country calling code
+pub(crate) fn normalize_prefix(prefix: &str, region: &Region) -> Result<String, PhoneBookError> {
+    let invalid = |reason| PhoneBookError::InvalidPrefix {
+        prefix: prefix.to_string(),
+        reason,
+    };
+
+    let (international, digits) = split_digits(prefix)
+        .ok_or_else(|| invalid("phone number prefix must only contain digits"))?;
+
+    if digits.is_empty() || digits.len() > 15 {
+        return Err(invalid(
+            "phone number prefix must be between 1 and 15 digits long",
+        ));
+    }
+
+    if international {
+        return Ok(digits);
+    }
+
+    Ok(format!(
+        "{}{}",
+        region.calling_code,
+        region.strip_trunk_prefix(&digits)
+    ))
+}
+
+impl FromStr for PhoneNumber {
+    type Err = PhoneBookError;
+
+    /// Parses the number, reading numbers without a country calling code as US numbers. Use
+    /// [`PhoneNumber::parse`] to read them as numbers of another region
+    fn from_str(s: &str) -> Result<PhoneNumber, PhoneBookError> {
+        PhoneNumber::parse(s, Region::default_region())
+    }
+}
+
+/// Reads a stored number, which is in E.164 form unless it was written before numbers had a
+/// country calling code, when it is a ten digit US number
+impl TryFrom<String> for PhoneNumber {
+    type Error = PhoneBookError;
+
+    fn try_from(s: String) -> Result<PhoneNumber, PhoneBookError> {
+        PhoneNumber::parse(&s, Region::default_region())
+    }
+}
+
+impl From<PhoneNumber> for String {
+    fn from(number: PhoneNumber) -> String {
+        number.0
+    }
+}
+
+impl Display for PhoneNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for PhoneNumber {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Lets sets of phone numbers be queried with a `&str`, the derived `Hash` and `PartialEq` only
+/// look at the E.164 string so this is consistent with them
+impl Borrow<str> for PhoneNumber {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Strips punctuation and a leading `+`, returning whether the `+` was present along with the
+/// remaining digits. `None` is returned if anything other than digits is left over
+fn split_digits(s: &str) -> Option<(bool, String)> {
+    let s = s.trim();
+    let (international, rest) = match s.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+
+    let digits = rest
+        .chars()
+        .filter(|c| !is_punctuation(*c))
+        .collect::<String>();
+
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((international, digits))
+}
+
+/// Characters people commonly use to group the digits of a phone number
+fn is_punctuation(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '-' | '.' | '(' | ')')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(code: &str) -> &'static Region {
+        Region::find(code).unwrap()
+    }
+
+    fn parse(s: &str, code: &str) -> String {
+        PhoneNumber::parse(s, region(code)).unwrap().to_string()
+    }
+
+    /// The reason parsing `s` in the region failed
+    fn reason(s: &str, code: &str) -> &'static str {
+        match PhoneNumber::parse(s, region(code)) {
+            Err(PhoneBookError::InvalidPhoneNumber { reason, .. }) => reason,
+            other => panic!("expected {:?} to be invalid, got {:?}", s, other),
+        }
+    }
+
+    #[test]
+    fn national_numbers_get_the_region_calling_code() {
+        assert_eq!(parse("(555) 123-4567", "US"), "+15551234567");
+        assert_eq!(parse("555.123.4567", "US"), "+15551234567");
+        assert_eq!(parse("0412 345 678", "AU"), "+61412345678");
+        assert_eq!(parse("020 7946 0958", "GB"), "+442079460958");
+        assert_eq!(parse("8123 4567", "SG"), "+6581234567");
+    }
+
+    #[test]
+    fn international_numbers_ignore_the_region() {
+        assert_eq!(parse("+61 412 345 678", "US"), "+61412345678");
+        assert_eq!(parse("+1 555 123 4567", "AU"), "+15551234567");
+        assert_eq!(parse("+353 1 234 5678", "US"), "+35312345678");
+        assert_eq!(parse("+39 06 1234 5678", "US"), "+390612345678");
+        assert_eq!(parse("+7 916 123 45 67", "US"), "+79161234567");
+    }
+
+    #[test]
+    fn north_american_numbers_can_be_dialled_with_the_trunk_prefix() {
+        assert_eq!(parse("1 555 000 0010", "US"), "+15550000010");
+        assert_eq!(parse("1 (416) 555-0123", "CA"), "+14165550123");
+        assert_eq!(parse("555 000 0010", "US"), "+15550000010");
+    }
+
+    #[test]
+    fn numbers_from_countries_missing_from_the_table_keep_to_e164_lengths() {
+        let armenia = PhoneNumber::parse("+374 10 123456", region("US")).unwrap();
+        assert_eq!(armenia.as_str(), "+37410123456");
+        assert!(armenia.region().is_none());
+        assert_eq!(armenia.national(), "10123456");
+        assert_eq!(armenia.international(), "+374 10123456");
+
+        let wrong_length = "phone number has the wrong number of digits for its country";
+        assert_eq!(reason("+374 123", "US"), wrong_length);
+        assert_eq!(reason("+374 1234 5678 90123", "US"), wrong_length);
+    }
+
+    #[test]
+    fn legacy_ten_digit_numbers_are_us_numbers() {
+        let number: PhoneNumber = "5551234567".parse().unwrap();
+        assert_eq!(number.as_str(), "+15551234567");
+        assert_eq!(number.region().unwrap().code, "US");
+
+        let stored = PhoneNumber::try_from("5551234567".to_string()).unwrap();
+        assert_eq!(stored, number);
+        let stored = PhoneNumber::try_from("+15551234567".to_string()).unwrap();
+        assert_eq!(stored, number);
+    }
+
+    #[test]
+    fn wrong_lengths_are_rejected() {
+        let wrong_length = "phone number has the wrong number of digits for its country";
+        assert_eq!(reason("555123456", "US"), wrong_length);
+        assert_eq!(reason("55512345678", "US"), wrong_length);
+        assert_eq!(reason("0412 345 67", "AU"), wrong_length);
+        assert_eq!(reason("+61 412 345 6789", "US"), wrong_length);
+        assert_eq!(reason("", "US"), wrong_length);
+    }
+
+    #[test]
+    fn unknown_calling_codes_and_letters_are_rejected() {
+        assert_eq!(
+            reason("+0 123 456 789", "US"),
+            "phone number has an unknown country calling code"
+        );
+        assert_eq!(
+            reason("+", "US"),
+            "phone number has an unknown country calling code"
+        );
+        assert_eq!(
+            reason("555-CALL-NOW", "US"),
+            "phone number must only contain digits"
+        );
+        assert_eq!(
+            reason("+1 555 123 4567 ext 2", "US"),
+            "phone number must only contain digits"
+        );
+    }
+
+    #[test]
+    fn numbers_are_formatted_for_their_region() {
+        let us = PhoneNumber::parse("5551234567", region("US")).unwrap();
+        assert_eq!(us.national(), "555 123 4567");
+        assert_eq!(us.international(), "+1 555 123 4567");
+
+        let au = PhoneNumber::parse("0412345678", region("AU")).unwrap();
+        assert_eq!(au.national_number(), "412345678");
+        assert_eq!(au.national(), "0412 345 678");
+        assert_eq!(au.international(), "+61 412 345 678");
+
+        let fr = PhoneNumber::parse("+33 6 12 34 56 78", region("US")).unwrap();
+        assert_eq!(fr.national(), "06 12 34 56 78");
+        assert_eq!(fr.international(), "+33 6 12 34 56 78");
+    }
+
+    #[test]
+    fn prefixes_are_normalized_to_e164_digits() {
+        assert_eq!(normalize_prefix("555", region("US")).unwrap(), "1555");
+        assert_eq!(normalize_prefix("1 555", region("US")).unwrap(), "1555");
+        assert_eq!(normalize_prefix("041", region("AU")).unwrap(), "6141");
+        assert_eq!(normalize_prefix("+44 20", region("AU")).unwrap(), "4420");
+        assert_eq!(
+            normalize_prefix("(555) 12", region("US")).unwrap(),
+            "155512"
+        );
+    }
+
+    #[test]
+    fn invalid_prefixes_are_rejected() {
+        let reason = |prefix| match normalize_prefix(prefix, region("US")) {
+            Err(PhoneBookError::InvalidPrefix { reason, .. }) => reason,
+            other => panic!("expected {:?} to be invalid, got {:?}", prefix, other),
+        };
+
+        let length = "phone number prefix must be between 1 and 15 digits long";
+        assert_eq!(reason(""), length);
+        assert_eq!(reason("+"), length);
+        assert_eq!(reason("1234567890123456"), length);
+        assert_eq!(
+            reason("55a"),
+            "phone number prefix must only contain digits"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Keys for names that sound alike, so a name spelled the way it was heard over the phone can
+//! still be found: "Smyth" and "Smith" share the key S530, "Steven" and "Stephen" S315.
+//!
+//! Keys are American Soundex codes taken from the letters of the name once accents are folded,
+//! names without any Latin letters have no key and are never matched phonetically
+
+use crate::collation;
+
+/// The Soundex code of a name, a letter followed by three digits
+pub(crate) fn soundex(name: &str) -> Option<String> {
+    let mut letters = collation::fold(name)
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    let first = letters.next()?;
+    let mut key = first.to_ascii_uppercase().to_string();
+    let mut last = digit(first);
+
+    for letter in letters {
+        match letter {
+            // h and w don't separate letters with the same code, "Ashcraft" is A261
+            'h' | 'w' => {}
+            'a' | 'e' | 'i' | 'o' | 'u' | 'y' => last = None,
+            _ => {
+                let code = digit(letter);
+                if code != last {
+                    key.extend(code);
+                }
+                last = code;
+            }
+        }
+
+        if key.len() == 4 {
+            break;
+        }
+    }
+
+    Some(format!("{:0<4}", key))
+}
+
+fn digit(letter: char) -> Option<char> {
+    let digit = match letter {
+        'b' | 'f' | 'p' | 'v' => '1',
+        'c' | 'g' | 'j' | 'k' | 'q' | 's' | 'x' | 'z' => '2',
+        'd' | 't' => '3',
+        'l' => '4',
+        'm' | 'n' => '5',
+        'r' => '6',
+        _ => return None,
+    };
+
+    Some(digit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> String {
+        soundex(name).unwrap()
+    }
+
+    #[test]
+    fn soundex_matches_the_standard_examples() {
+        assert_eq!(key("Robert"), "R163");
+        assert_eq!(key("Rupert"), "R163");
+        assert_eq!(key("Rubin"), "R150");
+        assert_eq!(key("Ashcraft"), "A261");
+        assert_eq!(key("Ashcroft"), "A261");
+        assert_eq!(key("Tymczak"), "T522");
+        assert_eq!(key("Pfister"), "P236");
+        assert_eq!(key("Honeyman"), "H555");
+    }
+
+    #[test]
+    fn short_names_are_padded_with_zeros() {
+        assert_eq!(key("Lee"), "L000");
+        assert_eq!(key("Ng"), "N200");
+    }
+
+    #[test]
+    fn accents_and_punctuation_are_ignored() {
+        assert_eq!(key("Zoë"), key("Zoe"));
+        assert_eq!(key("O'Brien"), key("OBrien"));
+        assert_eq!(soundex("李"), None);
+        assert_eq!(soundex(""), None);
+    }
+}
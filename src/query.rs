@@ -0,0 +1,451 @@
+//! A small query language for combining searches on several fields, such as
+//! `last:smith AND city:melbourne AND NOT phone:04*`.
+//!
+//! A term is a value, optionally preceded by the field to search and a colon. Without a field the
+//! value is searched for in every field, and the `name` field searches the first and last names.
+//! Values can be quoted to include spaces, parentheses or a colon, and an unquoted value ending
+//! in `*` matches every value starting with the rest of it. Terms are combined with `AND`, `OR`
+//! and `NOT` in upper case, terms next to each other without an operator must both match, and
+//! `AND` binds more tightly than `OR` unless parentheses say otherwise
+
+use crate::{ContactField, PhoneBookError, Result};
+use std::str::FromStr;
+
+/// A parsed query, see the [module documentation](self) for the syntax
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Query {
+    Term(Term),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+/// Matches contacts with any of `fields` matching `value`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Term {
+    pub fields: Vec<ContactField>,
+    pub value: String,
+    /// Whether values starting with `value` match as well
+    pub prefix: bool,
+}
+
+impl FromStr for Query {
+    type Err = PhoneBookError;
+
+    fn from_str(s: &str) -> Result<Query> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            next: 0,
+            end: s.chars().count() + 1,
+        };
+
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some((column, Token::Close)) => Err(invalid(*column, "unmatched `)`")),
+            Some((column, _)) => Err(invalid(*column, "expected `AND`, `OR` or the end")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term(Term),
+}
+
+/// Tokens along with the column each one starts at, counting characters from 1
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>> {
+    let mut lexer = Lexer {
+        chars: s.chars().collect(),
+        next: 0,
+    };
+    let mut tokens = Vec::new();
+
+    while let Some(c) = lexer.peek() {
+        let column = lexer.column();
+        match c {
+            c if c.is_whitespace() => {
+                lexer.next += 1;
+            }
+            '(' => {
+                lexer.next += 1;
+                tokens.push((column, Token::Open));
+            }
+            ')' => {
+                lexer.next += 1;
+                tokens.push((column, Token::Close));
+            }
+            _ => {
+                let (word, quoted) = lexer.word()?;
+                let token = match (word.as_str(), quoted) {
+                    ("AND", false) => Token::And,
+                    ("OR", false) => Token::Or,
+                    ("NOT", false) => Token::Not,
+                    _ => Token::Term(lexer.term(column, word, quoted)?),
+                };
+                tokens.push((column, token));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    next: usize,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.next).copied()
+    }
+
+    /// Column of the next character
+    fn column(&self) -> usize {
+        self.next + 1
+    }
+
+    /// Reads a quoted value or a run of characters up to whitespace, a parenthesis or a colon,
+    /// along with whether it was quoted
+    fn word(&mut self) -> Result<(String, bool)> {
+        let mut word = String::new();
+
+        if self.peek() == Some('"') {
+            let start = self.column();
+            self.next += 1;
+            loop {
+                let c = self
+                    .peek()
+                    .ok_or_else(|| invalid(start, "unterminated quote"))?;
+                self.next += 1;
+                match c {
+                    '"' => return Ok((word, true)),
+                    '\\' => {
+                        let column = self.column();
+                        let c = self
+                            .peek()
+                            .ok_or_else(|| invalid(column, "expected a character after `\\`"))?;
+                        self.next += 1;
+                        word.push(c);
+                    }
+                    _ => word.push(c),
+                }
+            }
+        }
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || matches!(c, '(' | ')' | ':' | '"') {
+                break;
+            }
+            word.push(c);
+            self.next += 1;
+        }
+
+        Ok((word, false))
+    }
+
+    /// Reads the rest of a term starting with `word` at `column`, which is the field name when a
+    /// colon follows it
+    fn term(&mut self, column: usize, word: String, quoted: bool) -> Result<Term> {
+        let (fields, value_column, value, quoted) = match self.peek() {
+            Some(':') if !quoted => {
+                self.next += 1;
+                let fields = fields(&word)
+                    .ok_or_else(|| invalid(column, format!("unknown field {:?}", word)))?;
+                let value_column = self.column();
+                if self.peek() == Some(':') {
+                    return Err(invalid(value_column, "unexpected `:`"));
+                }
+                let (value, quoted) = self.word()?;
+                (fields, value_column, value, quoted)
+            }
+            _ => (ContactField::ALL.to_vec(), column, word, quoted),
+        };
+
+        match self.peek() {
+            Some('"') => return Err(invalid(self.column(), "expected a space before `\"`")),
+            Some(':') => return Err(invalid(self.column(), "unexpected `:`")),
+            _ => {}
+        }
+        if value.is_empty() && !quoted {
+            return Err(invalid(value_column, "expected a value"));
+        }
+
+        let (value, prefix) = match value.strip_suffix('*') {
+            Some(value) if !quoted => (value.to_string(), true),
+            _ => (value, false),
+        };
+        if let Some(position) = value.chars().position(|c| c == '*').filter(|_| !quoted) {
+            return Err(invalid(
+                value_column + position,
+                "`*` can only be used at the end of a value",
+            ));
+        }
+
+        Ok(Term {
+            fields,
+            value,
+            prefix,
+        })
+    }
+}
+
+/// The fields searched for a field name in a query
+fn fields(name: &str) -> Option<Vec<ContactField>> {
+    let field = match name.to_lowercase().as_str() {
+        "name" => return Some(vec![ContactField::FirstName, ContactField::LastName]),
+        "first" | "first_name" => ContactField::FirstName,
+        "last" | "last_name" => ContactField::LastName,
+        "phone" | "phone_number" => ContactField::PhoneNumber,
+        "street" | "street_address" => ContactField::StreetAddress,
+        "city" => ContactField::City,
+        "state" => ContactField::State,
+        "postcode" => ContactField::Postcode,
+        "country" => ContactField::Country,
+        _ => return None,
+    };
+
+    Some(vec![field])
+}
+
+/// Recursive descent parser for
+///
+/// ```text
+/// or   = and ("OR" and)*
+/// and  = not ("AND"? not)*
+/// not  = "NOT" not | "(" or ")" | term
+/// ```
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    next: usize,
+    /// Column just past the end of the query, where running out of tokens is reported
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<&(usize, Token)> {
+        let token = self.tokens.get(self.next);
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut query = self.and()?;
+
+        while let Some((_, Token::Or)) = self.peek() {
+            self.next += 1;
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut query = self.not()?;
+
+        loop {
+            match self.peek() {
+                Some((_, Token::And)) => self.next += 1,
+                Some((_, Token::Open | Token::Not | Token::Term(_))) => {}
+                _ => return Ok(query),
+            }
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Query> {
+        let end = self.end;
+
+        match self.advance() {
+            Some((_, Token::Not)) => Ok(Query::Not(Box::new(self.not()?))),
+            Some((column, Token::Open)) => {
+                let column = *column;
+                let query = self.or()?;
+                match self.advance() {
+                    Some((_, Token::Close)) => Ok(query),
+                    _ => Err(invalid(column, "unmatched `(`")),
+                }
+            }
+            Some((_, Token::Term(term))) => Ok(Query::Term(term.clone())),
+            Some((column, token)) => Err(invalid(
+                *column,
+                format!("expected a search term but found {}", describe(token)),
+            )),
+            None => Err(invalid(end, "expected a search term")),
+        }
+    }
+}
+
+fn describe(token: &Token) -> &'static str {
+    match token {
+        Token::Open => "`(`",
+        Token::Close => "`)`",
+        Token::And => "`AND`",
+        Token::Or => "`OR`",
+        Token::Not => "`NOT`",
+        Token::Term(_) => "a search term",
+    }
+}
+
+fn invalid(column: usize, reason: impl Into<String>) -> PhoneBookError {
+    PhoneBookError::InvalidQuery {
+        column,
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Query {
+        s.parse().unwrap()
+    }
+
+    fn term(fields: &[ContactField], value: &str) -> Query {
+        Query::Term(Term {
+            fields: fields.to_vec(),
+            value: value.to_string(),
+            prefix: false,
+        })
+    }
+
+    fn last(value: &str) -> Query {
+        term(&[ContactField::LastName], value)
+    }
+
+    fn city(value: &str) -> Query {
+        term(&[ContactField::City], value)
+    }
+
+    fn and(a: Query, b: Query) -> Query {
+        Query::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Query, b: Query) -> Query {
+        Query::Or(Box::new(a), Box::new(b))
+    }
+
+    fn not(query: Query) -> Query {
+        Query::Not(Box::new(query))
+    }
+
+    /// The column and reason of the error parsing `s`
+    fn error(s: &str) -> (usize, String) {
+        match s.parse::<Query>() {
+            Err(PhoneBookError::InvalidQuery { column, reason }) => (column, reason),
+            other => panic!("expected {:?} to be invalid, got {:?}", s, other),
+        }
+    }
+
+    #[test]
+    fn and_binds_more_tightly_than_or() {
+        assert_eq!(
+            parse("last:smith OR last:jones AND city:perth"),
+            or(last("smith"), and(last("jones"), city("perth")))
+        );
+        assert_eq!(
+            parse("last:smith AND last:jones OR city:perth"),
+            or(and(last("smith"), last("jones")), city("perth"))
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_eq!(
+            parse("(last:smith OR last:jones) AND city:perth"),
+            and(or(last("smith"), last("jones")), city("perth"))
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term_only() {
+        assert_eq!(
+            parse("NOT last:smith AND city:perth"),
+            and(not(last("smith")), city("perth"))
+        );
+        assert_eq!(parse("NOT NOT city:perth"), not(not(city("perth"))));
+    }
+
+    #[test]
+    fn terms_without_an_operator_must_both_match() {
+        assert_eq!(
+            parse("last:smith city:perth OR city:hobart"),
+            or(and(last("smith"), city("perth")), city("hobart"))
+        );
+        assert_eq!(
+            parse("last:smith NOT city:perth"),
+            and(last("smith"), not(city("perth")))
+        );
+    }
+
+    #[test]
+    fn terms() {
+        assert_eq!(parse("smith"), term(&ContactField::ALL, "smith"));
+        assert_eq!(
+            parse("name:smith"),
+            term(&[ContactField::FirstName, ContactField::LastName], "smith")
+        );
+        assert_eq!(parse("CITY:\"Alice Springs\""), city("Alice Springs"));
+        assert_eq!(parse(r#"city:"say \"hi\"""#), city("say \"hi\""));
+        assert_eq!(parse("\"AND\""), term(&ContactField::ALL, "AND"));
+        assert_eq!(parse("city:\"perth*\""), city("perth*"));
+        assert_eq!(
+            parse("phone:04*"),
+            Query::Term(Term {
+                fields: vec![ContactField::PhoneNumber],
+                value: "04".to_string(),
+                prefix: true,
+            })
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_column_of_the_problem() {
+        let error = |s| {
+            let (column, _) = error(s);
+            column
+        };
+
+        assert_eq!(error("nickname:bob"), 1);
+        assert_eq!(error("last:smith AND"), 15);
+        assert_eq!(error("last:smith OR OR city:perth"), 15);
+        assert_eq!(error("(last:smith"), 1);
+        assert_eq!(error("last:smith)"), 11);
+        assert_eq!(error("city:\"perth"), 6);
+        assert_eq!(error("city:"), 6);
+        assert_eq!(error("city::perth"), 6);
+        assert_eq!(error("city:pe*rth"), 8);
+        assert_eq!(error("ŝmith AND ()"), 12);
+    }
+
+    #[test]
+    fn errors_say_what_went_wrong() {
+        assert_eq!(
+            error("nickname:bob"),
+            (1, "unknown field \"nickname\"".to_string())
+        );
+        assert_eq!(
+            error("last:smith OR AND"),
+            (15, "expected a search term but found `AND`".to_string())
+        );
+        assert_eq!(error(""), (1, "expected a search term".to_string()));
+        assert_eq!(error("(smith"), (1, "unmatched `(`".to_string()));
+        assert_eq!(error("smith)"), (6, "unmatched `)`".to_string()));
+        assert_eq!(
+            error("city:pe*rth"),
+            (8, "`*` can only be used at the end of a value".to_string())
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{PhoneBookError, Result};
+
+/// Offline numbering plan metadata for a single country, this only holds enough to validate the
+/// length of a number and to group its digits for display
+#[derive(Debug, PartialEq, Eq)]
+pub struct Region {
+    /// ISO 3166-1 alpha-2 code
+    pub code: &'static str,
+    pub calling_code: u16,
+    /// Digit(s) dialled before a national number that are not part of the number itself
+    pub trunk_prefix: Option<&'static str>,
+    /// Inclusive bounds on the length of the national significant number
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Sizes of the digit groups used when displaying the national significant number, any
+    /// remaining digits are added to the last group
+    groups: &'static [usize],
+}
+
+pub static REGIONS: [Region; 52] = [
+    region("US", 1, Some("1"), 10, 10, &[3, 3, 4]),
+    region("CA", 1, Some("1"), 10, 10, &[3, 3, 4]),
+    region("GB", 44, Some("0"), 9, 10, &[4, 6]),
+    region("AU", 61, Some("0"), 9, 9, &[3, 3, 3]),
+    region("NZ", 64, Some("0"), 8, 10, &[2, 3, 4]),
+    region("DE", 49, Some("0"), 7, 12, &[3, 4, 5]),
+    region("FR", 33, Some("0"), 9, 9, &[1, 2, 2, 2, 2]),
+    region("ES", 34, None, 9, 9, &[3, 3, 3]),
+    region("IE", 353, Some("0"), 7, 9, &[2, 3, 4]),
+    region("IN", 91, Some("0"), 10, 10, &[5, 5]),
+    region("CN", 86, Some("0"), 10, 11, &[3, 4, 4]),
+    region("JP", 81, Some("0"), 9, 10, &[2, 4, 4]),
+    region("SG", 65, None, 8, 8, &[4, 4]),
+    region("ZA", 27, Some("0"), 9, 9, &[2, 3, 4]),
+    region("IT", 39, None, 6, 11, &[3, 3, 4]),
+    region("RU", 7, Some("8"), 10, 10, &[3, 3, 2, 2]),
+    region("BR", 55, Some("0"), 10, 11, &[2, 5, 4]),
+    region("MX", 52, None, 10, 10, &[2, 4, 4]),
+    region("AR", 54, Some("0"), 10, 11, &[2, 4, 4]),
+    region("CL", 56, None, 9, 9, &[1, 4, 4]),
+    region("CO", 57, None, 10, 10, &[3, 3, 4]),
+    region("PE", 51, Some("0"), 8, 9, &[3, 3, 3]),
+    region("NL", 31, Some("0"), 9, 9, &[1, 2, 2, 2, 2]),
+    region("BE", 32, Some("0"), 8, 9, &[3, 2, 2, 2]),
+    region("CH", 41, Some("0"), 9, 9, &[2, 3, 2, 2]),
+    region("AT", 43, Some("0"), 4, 13, &[3, 4, 6]),
+    region("SE", 46, Some("0"), 6, 12, &[2, 3, 2, 2]),
+    region("NO", 47, None, 8, 8, &[3, 2, 3]),
+    region("DK", 45, None, 8, 8, &[2, 2, 2, 2]),
+    region("FI", 358, Some("0"), 5, 12, &[2, 3, 4]),
+    region("PL", 48, None, 9, 9, &[3, 3, 3]),
+    region("PT", 351, None, 9, 9, &[3, 3, 3]),
+    region("GR", 30, None, 10, 10, &[3, 3, 4]),
+    region("CZ", 420, None, 9, 9, &[3, 3, 3]),
+    region("HU", 36, Some("06"), 8, 9, &[2, 3, 4]),
+    region("RO", 40, Some("0"), 9, 9, &[3, 3, 3]),
+    region("UA", 380, Some("0"), 9, 9, &[2, 3, 4]),
+    region("TR", 90, Some("0"), 10, 10, &[3, 3, 4]),
+    region("IL", 972, Some("0"), 8, 9, &[2, 3, 4]),
+    region("AE", 971, Some("0"), 8, 9, &[2, 3, 4]),
+    region("SA", 966, Some("0"), 9, 9, &[2, 3, 4]),
+    region("EG", 20, Some("0"), 8, 10, &[2, 4, 4]),
+    region("NG", 234, Some("0"), 8, 10, &[3, 3, 4]),
+    region("KE", 254, Some("0"), 9, 9, &[3, 6]),
+    region("KR", 82, Some("0"), 8, 10, &[2, 4, 4]),
+    region("HK", 852, None, 8, 8, &[4, 4]),
+    region("TW", 886, Some("0"), 8, 9, &[1, 4, 4]),
+    region("PH", 63, Some("0"), 8, 10, &[3, 3, 4]),
+    region("ID", 62, Some("0"), 8, 12, &[3, 4, 5]),
+    region("MY", 60, Some("0"), 8, 10, &[2, 4, 4]),
+    region("TH", 66, Some("0"), 8, 9, &[2, 3, 4]),
+    region("VN", 84, Some("0"), 9, 10, &[2, 4, 4]),
+];
+
+/// Country calling codes with two digits. Only 1 and 7 have one digit and every other code has
+/// three, none of them starting with one of these, so the calling code of any international
+/// number can be split off without knowing the country it belongs to
+const TWO_DIGIT_CALLING_CODES: [&str; 44] = [
+    "20", "27", "30", "31", "32", "33", "34", "36", "39", "40", "41", "43", "44", "45", "46", "47",
+    "48", "49", "51", "52", "53", "54", "55", "56", "57", "58", "60", "61", "62", "63", "64", "65",
+    "66", "81", "82", "84", "86", "90", "91", "92", "93", "94", "95", "98",
+];
+
+/// The most digits an E.164 number can have, including its country calling code
+pub(crate) const MAX_DIGITS: usize = 15;
+
+/// The fewest digits a national significant number is accepted with for a country missing from
+/// [`REGIONS`], the shortest numbering plans in use have four
+pub(crate) const MIN_NATIONAL_DIGITS: usize = 4;
+
+const fn region(
+    code: &'static str,
+    calling_code: u16,
+    trunk_prefix: Option<&'static str>,
+    min_length: usize,
+    max_length: usize,
+    groups: &'static [usize],
+) -> Region {
+    Region {
+        code,
+        calling_code,
+        trunk_prefix,
+        min_length,
+        max_length,
+        groups,
+    }
+}
+
+impl Region {
+    /// Looks up a region by its two letter code, ignoring case
+    pub fn find(code: &str) -> Result<&'static Region> {
+        REGIONS
+            .iter()
+            .find(|region| region.code.eq_ignore_ascii_case(code.trim()))
+            .ok_or_else(|| PhoneBookError::UnknownRegion(code.to_string()))
+    }
+
+    /// Splits an international number into its region and national significant number, `None`
+    /// if its calling code is invalid or belongs to a country missing from [`REGIONS`]
+    pub fn split_calling_code(digits: &str) -> Option<(&'static Region, &str)> {
+        let (calling_code, national_number) = split_calling_code(digits)?;
+
+        Some((Region::with_calling_code(calling_code)?, national_number))
+    }
+
+    /// The first region listed with the calling code, countries sharing a code such as the US
+    /// and Canada resolve to the first of them
+    pub(crate) fn with_calling_code(calling_code: &str) -> Option<&'static Region> {
+        let calling_code = calling_code.parse::<u16>().ok()?;
+
+        REGIONS
+            .iter()
+            .find(|region| region.calling_code == calling_code)
+    }
+
+    /// The US, where numbers without a country calling code are from unless another region is
+    /// given. The README originally assumed ten digit numbers without country codes, so this is
+    /// also the region phone book files written before numbers were stored in E.164 form are
+    /// read with, whatever region numbers are entered in
+    pub fn default_region() -> &'static Region {
+        &REGIONS[0]
+    }
+
+    pub(crate) fn is_valid_length(&self, national_number: &str) -> bool {
+        (self.min_length..=self.max_length).contains(&national_number.len())
+    }
+
+    /// Removes the trunk prefix from a nationally dialled number if it has one
+    pub(crate) fn strip_trunk_prefix<'a>(&self, digits: &'a str) -> &'a str {
+        self.trunk_prefix
+            .and_then(|prefix| digits.strip_prefix(prefix))
+            .unwrap_or(digits)
+    }
+
+    /// Splits the national significant number into space separated groups
+    pub(crate) fn group(&self, national_number: &str) -> String {
+        let mut groups = Vec::new();
+        let mut rest = national_number;
+
+        for (index, size) in self.groups.iter().enumerate() {
+            if rest.is_empty() {
+                break;
+            }
+
+            let size = if index == self.groups.len() - 1 {
+                rest.len()
+            } else {
+                (*size).min(rest.len())
+            };
+
+            groups.push(&rest[..size]);
+            rest = &rest[size..];
+        }
+
+        groups.join(" ")
+    }
+}
+
+/// Splits an international number into its country calling code and national significant
+/// number, `None` if it is empty or starts with 0 which no calling code does
+pub(crate) fn split_calling_code(digits: &str) -> Option<(&str, &str)> {
+    let length = match digits.as_bytes().first()? {
+        b'0' => return None,
+        b'1' | b'7' => 1,
+        _ if TWO_DIGIT_CALLING_CODES.contains(&digits.get(..2)?) => 2,
+        _ => 3,
+    };
+
+    Some((digits.get(..length)?, &digits[length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_are_found_by_code_ignoring_case() {
+        assert_eq!(Region::find("au").unwrap().calling_code, 61);
+        assert_eq!(Region::find(" GB ").unwrap().calling_code, 44);
+        assert!(matches!(
+            Region::find("XX"),
+            Err(PhoneBookError::UnknownRegion(code)) if code == "XX"
+        ));
+    }
+
+    #[test]
+    fn calling_codes_of_every_length_are_split_off() {
+        let split = |digits| {
+            let (region, national) = Region::split_calling_code(digits).unwrap();
+            (region.code, national)
+        };
+
+        assert_eq!(split("15551234567"), ("US", "5551234567"));
+        assert_eq!(split("61412345678"), ("AU", "412345678"));
+        assert_eq!(split("35312345678"), ("IE", "12345678"));
+        assert_eq!(split("79161234567"), ("RU", "9161234567"));
+        assert!(Region::split_calling_code("37410123456").is_none());
+        assert!(Region::split_calling_code("").is_none());
+    }
+
+    #[test]
+    fn calling_codes_of_countries_missing_from_the_table_are_split_off() {
+        assert_eq!(split_calling_code("37410123456"), Some(("374", "10123456")));
+        assert_eq!(split_calling_code("2693123456"), Some(("269", "3123456")));
+        assert_eq!(split_calling_code("2012345678"), Some(("20", "12345678")));
+        assert_eq!(split_calling_code("0123456"), None);
+        assert_eq!(split_calling_code("9"), None);
+    }
+
+    #[test]
+    fn trunk_prefix_is_only_stripped_where_there_is_one() {
+        let au = Region::find("AU").unwrap();
+        assert_eq!(au.strip_trunk_prefix("0412345678"), "412345678");
+        assert_eq!(au.strip_trunk_prefix("412345678"), "412345678");
+
+        let us = Region::find("US").unwrap();
+        assert_eq!(us.strip_trunk_prefix("15551234567"), "5551234567");
+        assert_eq!(us.strip_trunk_prefix("0412345678"), "0412345678");
+    }
+
+    #[test]
+    fn extra_digits_are_added_to_the_last_group() {
+        let de = Region::find("DE").unwrap();
+        assert_eq!(de.group("301234"), "301 234");
+        assert_eq!(de.group("30123456789012"), "301 2345 6789012");
+
+        let gb = Region::find("GB").unwrap();
+        assert_eq!(gb.group("2079460958"), "2079 460958");
+        assert_eq!(gb.group(""), "");
+    }
+}
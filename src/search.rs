@@ -0,0 +1,228 @@
+//! Typo tolerant search over the names, city and street address of contacts, ranking every
+//! contact within a few edits of the search by how closely it matches.
+//!
+//! Values are compared once normalized with [`MatchPolicy::Normalized`], against the whole value
+//! and each of its words, so "melborne" finds "Melbourne" and "smiht" finds "12 Smith St". The
+//! first and last names are also compared together, so "jon smith" finds "John Smith"
+
+use crate::{Contact, ContactField, MatchPolicy, SortKey};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// The fields a ranked search looks at
+const FIELDS: [ContactField; 4] = [
+    ContactField::FirstName,
+    ContactField::LastName,
+    ContactField::City,
+    ContactField::StreetAddress,
+];
+
+/// Options for [`crate::PhoneBook::search_ranked`]
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    /// Most insertions, deletions, substitutions and transpositions of adjacent letters a value
+    /// can be from the search and still match
+    pub max_distance: usize,
+    /// Only return the best matches up to this many
+    pub limit: Option<usize>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            max_distance: 2,
+            limit: None,
+        }
+    }
+}
+
+/// A contact found by [`crate::PhoneBook::search_ranked`]
+#[derive(Debug)]
+pub struct RankedMatch<'a> {
+    pub contact: &'a Contact,
+    /// Every field within the maximum distance of the search
+    pub fields: Vec<ContactField>,
+    /// Edits between the search and the closest matching value
+    pub distance: usize,
+    /// How closely the contact matches from 0 to 1, an exact match scores 1
+    pub score: f64,
+}
+
+/// Ranks the contacts within `options.max_distance` of `search`, best match first. Contacts
+/// with the same score are ranked by how many of their fields matched then by name
+pub(crate) fn rank<'a>(
+    contacts: impl Iterator<Item = &'a Contact>,
+    search: &str,
+    options: SearchOptions,
+) -> Vec<RankedMatch<'a>> {
+    let search = MatchPolicy::Normalized.key(search);
+    if search.is_empty() {
+        return Vec::new();
+    }
+
+    let mut matches = contacts
+        .filter_map(|contact| rank_contact(contact, &search, options.max_distance))
+        .collect::<Vec<_>>();
+
+    matches.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| b.fields.len().cmp(&a.fields.len()))
+            .then_with(|| SortKey::LastName.compare(a.contact, b.contact))
+            .then_with(|| a.contact.id.cmp(&b.contact.id))
+    });
+    matches.truncate(options.limit.unwrap_or(usize::MAX));
+
+    matches
+}
+
+fn rank_contact<'a>(
+    contact: &'a Contact,
+    search: &str,
+    max_distance: usize,
+) -> Option<RankedMatch<'a>> {
+    let values = contact
+        .fields()
+        .into_iter()
+        .filter(|(field, _)| FIELDS.contains(field))
+        .map(|(field, value)| (vec![field], MatchPolicy::Normalized.key(value), true))
+        .chain([(
+            vec![ContactField::FirstName, ContactField::LastName],
+            MatchPolicy::Normalized.key(&format!("{} {}", contact.first_name, contact.last_name)),
+            // the words of the full name are already compared with each name
+            false,
+        )]);
+
+    let mut fields = Vec::new();
+    let mut best: Option<(usize, f64)> = None;
+
+    for (value_fields, value, with_words) in values {
+        let closest = candidates(&value, with_words)
+            .map(|candidate| {
+                let distance = edit_distance(search, candidate);
+                (distance, similarity(search, candidate, distance))
+            })
+            .filter(|(distance, _)| *distance <= max_distance)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+
+        if let Some((distance, score)) = closest {
+            for field in value_fields {
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+            if best.is_none_or(|(_, best)| score > best) {
+                best = Some((distance, score));
+            }
+        }
+    }
+
+    let (distance, score) = best?;
+    fields.sort_by_key(|field| ContactField::ALL.iter().position(|f| f == field));
+
+    Some(RankedMatch {
+        contact,
+        fields,
+        distance,
+        score,
+    })
+}
+
+/// The whole value and each of its words if `with_words` is set
+fn candidates(value: &str, with_words: bool) -> impl Iterator<Item = &str> {
+    let words = value
+        .split(' ')
+        .filter(move |_| with_words && value.contains(' '));
+
+    [value]
+        .into_iter()
+        .chain(words)
+        .filter(|candidate| !candidate.is_empty())
+}
+
+/// One minus the share of the longer string that had to be edited
+fn similarity(a: &str, b: &str, distance: usize) -> f64 {
+    let length = a.chars().count().max(b.chars().count()).max(1);
+    1.0 - distance as f64 / length as f64
+}
+
+/// The Damerau–Levenshtein distance between `a` and `b`, the number of single character
+/// insertions, deletions, substitutions and transpositions of adjacent characters turning `a`
+/// into `b`. Characters can still be edited after being transposed, so "ca" is two edits from
+/// "abc" rather than the three of the restricted optimal string alignment distance
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    // larger than any distance, for the border around the table
+    let far = a.len() + b.len();
+
+    // distances from each prefix of `a` to each prefix of `b`, offset by one row and column for
+    // the border
+    let mut rows = vec![vec![far; b.len() + 2]; a.len() + 2];
+    for i in 0..=a.len() {
+        rows[i + 1][1] = i;
+    }
+    for j in 0..=b.len() {
+        rows[1][j + 1] = j;
+    }
+
+    // the last row of the table where each character of `a` was seen
+    let mut last_row = HashMap::new();
+    for i in 1..=a.len() {
+        // the last column in this row where the characters matched
+        let mut last_match = 0;
+
+        for j in 1..=b.len() {
+            let k = last_row.get(&b[j - 1]).copied().unwrap_or(0);
+            let l = last_match;
+
+            let cost = match a[i - 1] == b[j - 1] {
+                true => {
+                    last_match = j;
+                    0
+                }
+                false => 1,
+            };
+
+            rows[i + 1][j + 1] = (rows[i][j] + cost)
+                .min(rows[i + 1][j] + 1)
+                .min(rows[i][j + 1] + 1)
+                // transposes the characters last matched at k and l, editing everything between
+                .min(rows[k][l] + (i - k - 1) + 1 + (j - l - 1));
+        }
+
+        last_row.insert(a[i - 1], i);
+    }
+
+    rows[a.len() + 1][b.len() + 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_each_kind_of_edit() {
+        assert_eq!(edit_distance("smith", "smith"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("smith", "smyth"), 1);
+        assert_eq!(edit_distance("smith", "smiths"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("zoë", "zoe"), 1);
+    }
+
+    #[test]
+    fn transpositions_are_one_edit() {
+        assert_eq!(edit_distance("smiht", "smith"), 1);
+        assert_eq!(edit_distance("jhon", "john"), 1);
+    }
+
+    #[test]
+    fn transposed_characters_can_be_edited_again() {
+        // optimal string alignment can't insert between the transposed characters and gives 3
+        assert_eq!(edit_distance("ca", "abc"), 2);
+        assert_eq!(edit_distance("abc", "ca"), 2);
+    }
+}
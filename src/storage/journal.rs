@@ -0,0 +1,492 @@
+use crate::storage::{load_snapshot, save_snapshot, Snapshot, Storage};
+use crate::{atomic, Contact, ContactId, PhoneBookError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Keeps a json snapshot in the same format as [`crate::storage::JsonStorage`] and appends each
+/// change to a journal next to it instead of rewriting the snapshot. The journal is replayed over
+/// the snapshot on open and folded back into it by `compact`, which rewrites the snapshot from
+/// the phone book's contacts.
+///
+/// Every journal record is a line holding a crc32 of the rest of the line, the generation of the
+/// snapshot it applies to and the record as json. A record at the end of the journal that is
+/// incomplete or fails its checksum was torn by a crash while appending, it is ignored and
+/// overwritten by the next commit.
+///
+/// Each snapshot is stamped with a new random generation when it is written. A journal whose
+/// records belong to another generation was left behind by a crash or failure between writing a
+/// newer snapshot and removing the journal, the snapshot already holds or has since undone its
+/// changes so it is ignored and overwritten by the next commit.
+///
+/// A snapshot and its journal are one phone book whichever backend opens them, so the json
+/// backend and [`crate::PhoneBook::new_from_file`] load and save them the same way, through the
+/// storage module's `load_snapshot` and `save_snapshot`
+#[derive(Debug)]
+pub struct JournalStorage {
+    path: PathBuf,
+    backup: bool,
+    /// Contacts read on open, handed over by the first `load` so they aren't kept twice
+    loaded: Option<Vec<Contact>>,
+    /// Encoded records waiting for the next commit
+    pending: Vec<String>,
+    /// Generation of the snapshot the journal applies to
+    generation: u64,
+    /// Length of the journal up to the end of its last valid record
+    journal_length: u64,
+    /// Set for new storage so the empty snapshot is written on the first commit
+    write_snapshot: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Record {
+    Put { contact: Contact },
+    Delete { id: ContactId },
+}
+
+impl JournalStorage {
+    pub fn open(path: &Path, backup: bool) -> Result<JournalStorage> {
+        let (contacts, generation, journal_length) = read(path)?;
+
+        Ok(JournalStorage {
+            path: path.to_path_buf(),
+            backup,
+            loaded: Some(contacts.into_values().collect()),
+            pending: Vec::new(),
+            generation,
+            journal_length,
+            write_snapshot: false,
+        })
+    }
+
+    /// An empty phone book, the snapshot is written and any old journal cleared on the first
+    /// commit
+    pub fn create(path: &Path, backup: bool) -> JournalStorage {
+        JournalStorage {
+            path: path.to_path_buf(),
+            backup,
+            loaded: None,
+            pending: Vec::new(),
+            generation: 0,
+            journal_length: 0,
+            write_snapshot: true,
+        }
+    }
+
+    fn append(&mut self, record: &Record) -> Result<()> {
+        self.pending.push(encode(self.generation, record)?);
+
+        Ok(())
+    }
+
+    fn write_snapshot(&mut self, contacts: &mut dyn Iterator<Item = &Contact>) -> Result<()> {
+        self.generation = save_snapshot(&self.path, self.backup, contacts)?;
+
+        self.journal_length = 0;
+        self.pending.clear();
+        self.write_snapshot = false;
+
+        Ok(())
+    }
+}
+
+impl Storage for JournalStorage {
+    fn load(&mut self) -> Result<Vec<Contact>> {
+        match self.loaded.take() {
+            Some(contacts) => Ok(contacts),
+            None if self.write_snapshot => Ok(Vec::new()),
+            None => load_snapshot(&self.path),
+        }
+    }
+
+    fn put(&mut self, contact: &Contact) -> Result<()> {
+        self.append(&Record::Put {
+            contact: contact.clone(),
+        })
+    }
+
+    fn delete(&mut self, id: ContactId) -> Result<()> {
+        self.append(&Record::Delete { id })
+    }
+
+    /// Appends the pending records to the journal and flushes it to disk, `contacts` are only
+    /// written for new storage which has no snapshot yet
+    fn commit(&mut self, contacts: &mut dyn Iterator<Item = &Contact>) -> Result<()> {
+        if self.write_snapshot {
+            return self.write_snapshot(contacts);
+        }
+
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut journal = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(journal_path(&self.path))?;
+
+        // drops any torn record left at the end by an earlier crash
+        journal.set_len(self.journal_length)?;
+        journal.seek(SeekFrom::End(0))?;
+
+        let records = self.pending.concat();
+        journal.write_all(records.as_bytes())?;
+        journal.sync_data()?;
+
+        self.journal_length += records.len() as u64;
+        self.pending.clear();
+
+        Ok(())
+    }
+
+    /// Writes every contact to a fresh snapshot and removes the journal
+    fn compact(&mut self, contacts: &mut dyn Iterator<Item = &Contact>) -> Result<()> {
+        self.write_snapshot(contacts)
+    }
+}
+
+/// The contacts in the snapshot at `path` with its journal replayed over them, along with the
+/// snapshot's generation and the length of the journal up to the end of its last valid record
+pub(super) fn read(path: &Path) -> Result<(HashMap<ContactId, Contact>, u64, u64)> {
+    let reader = BufReader::new(File::open(path)?);
+    let stored: Snapshot<Contact> = serde_json::from_reader(reader)?;
+
+    let mut contacts = stored
+        .contacts
+        .into_iter()
+        .map(|contact| (contact.id, contact))
+        .collect();
+    let journal_length = replay(path, stored.generation, &mut contacts)?;
+
+    Ok((contacts, stored.generation, journal_length))
+}
+
+/// Applies the journal for the snapshot at `path` to `contacts` if it belongs to the snapshot's
+/// `generation`, returning the length of the journal up to the end of its last valid record. A
+/// journal from another generation is ignored and has a length of 0 so the next commit replaces it
+fn replay(path: &Path, generation: u64, contacts: &mut HashMap<ContactId, Contact>) -> Result<u64> {
+    let journal = match fs::read(journal_path(path)) {
+        Ok(journal) => journal,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    let mut torn_at = None;
+    for (index, line) in journal.split_inclusive(|byte| *byte == b'\n').enumerate() {
+        match decode(line) {
+            Some(record) if torn_at.is_none() => {
+                records.push(record);
+                offset += line.len();
+            }
+            // a valid record after a broken one means the journal was damaged some other way
+            // than a crash while appending
+            Some(_) => {
+                return Err(PhoneBookError::Storage(format!(
+                    "journal record {} is corrupt",
+                    torn_at.unwrap_or_default() + 1
+                )))
+            }
+            None => torn_at = torn_at.or(Some(index)),
+        }
+    }
+
+    // every record is appended against the same snapshot, so the first one decides whether the
+    // journal is stale
+    if records
+        .first()
+        .is_some_and(|(record_generation, _)| *record_generation != generation)
+    {
+        return Ok(0);
+    }
+
+    for (index, (record_generation, record)) in records.into_iter().enumerate() {
+        if record_generation != generation {
+            return Err(PhoneBookError::Storage(format!(
+                "journal record {} belongs to another snapshot",
+                index + 1
+            )));
+        }
+        apply(contacts, record);
+    }
+
+    Ok(offset as u64)
+}
+
+fn apply(contacts: &mut HashMap<ContactId, Contact>, record: Record) {
+    match record {
+        Record::Put { contact } => {
+            contacts.insert(contact.id, contact);
+        }
+        Record::Delete { id } => {
+            contacts.remove(&id);
+        }
+    }
+}
+
+/// Path of the journal kept for the snapshot at `path`
+fn journal_path(path: &Path) -> PathBuf {
+    atomic::suffixed_path(path, ".journal")
+}
+
+/// Removes the journal for the snapshot at `path` if there is one
+pub(super) fn remove_journal(path: &Path) -> Result<()> {
+    match fs::remove_file(journal_path(path)) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+fn encode(generation: u64, record: &Record) -> Result<String> {
+    let body = format!("{:016x} {}", generation, serde_json::to_string(record)?);
+
+    Ok(format!("{:08x} {}\n", crc32(body.as_bytes()), body))
+}
+
+/// Decodes a journal line into the generation it was appended against and its record, returning
+/// `None` if it is incomplete or its checksum doesn't match
+fn decode(line: &[u8]) -> Option<(u64, Record)> {
+    let line = line.strip_suffix(b"\n")?;
+    let line = std::str::from_utf8(line).ok()?;
+    let (checksum, body) = line.split_once(' ')?;
+
+    if u32::from_str_radix(checksum, 16).ok()? != crc32(body.as_bytes()) {
+        return None;
+    }
+
+    let (generation, json) = body.split_once(' ')?;
+    let generation = u64::from_str_radix(generation, 16).ok()?;
+
+    Some((generation, serde_json::from_str(json).ok()?))
+}
+
+/// The standard CRC-32 used by zip and png
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ScratchDir;
+
+    fn contact(first_name: &str) -> Contact {
+        Contact::new(
+            first_name.to_string(),
+            "Smith".to_string(),
+            Vec::new(),
+            None,
+        )
+    }
+
+    /// A snapshot holding no contacts and a journal putting each of `contacts`
+    fn journal_with(dir: &Path, contacts: &[&Contact]) -> PathBuf {
+        let path = dir.join("contacts.json");
+        let mut storage = JournalStorage::create(&path, false);
+        storage.commit(&mut std::iter::empty()).unwrap();
+        for contact in contacts {
+            storage.put(contact).unwrap();
+        }
+        storage.commit(&mut contacts.iter().copied()).unwrap();
+        path
+    }
+
+    fn names(storage: &mut JournalStorage) -> Vec<String> {
+        let mut names = storage
+            .load()
+            .unwrap()
+            .into_iter()
+            .map(|contact| contact.first_name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let john = contact("John");
+        let line = encode(
+            7,
+            &Record::Put {
+                contact: john.clone(),
+            },
+        )
+        .unwrap();
+
+        match decode(line.as_bytes()) {
+            Some((7, Record::Put { contact })) => {
+                assert_eq!(contact.id, john.id);
+                assert_eq!(contact.first_name, "John");
+            }
+            _ => panic!("expected the put record back"),
+        }
+        assert!(matches!(
+            decode(encode(7, &Record::Delete { id: john.id }).unwrap().as_bytes()),
+            Some((7, Record::Delete { id })) if id == john.id
+        ));
+    }
+
+    #[test]
+    fn records_failing_their_checksum_are_rejected() {
+        let line = encode(
+            7,
+            &Record::Put {
+                contact: contact("John"),
+            },
+        )
+        .unwrap();
+
+        assert!(decode(line.replace("John", "Jane").as_bytes()).is_none());
+        assert!(decode(line.trim_end().as_bytes()).is_none());
+        assert!(decode(&line.as_bytes()[..line.len() / 2]).is_none());
+        assert!(decode(b"not a record\n").is_none());
+    }
+
+    #[test]
+    fn journal_is_replayed_over_the_snapshot() {
+        let dir = ScratchDir::new("journal_replayed");
+        let (john, jane) = (contact("John"), contact("Jane"));
+        let path = journal_with(&dir, &[&john, &jane]);
+
+        let mut storage = JournalStorage::open(&path, false).unwrap();
+        storage.delete(john.id).unwrap();
+        storage.commit(&mut [&jane].into_iter()).unwrap();
+
+        let mut storage = JournalStorage::open(&path, false).unwrap();
+        assert_eq!(names(&mut storage), ["Jane"]);
+    }
+
+    #[test]
+    fn torn_final_record_is_ignored_and_overwritten() {
+        let dir = ScratchDir::new("journal_torn");
+        let path = journal_with(&dir, &[&contact("John")]);
+
+        let generation = JournalStorage::open(&path, false).unwrap().generation;
+        let torn = encode(
+            generation,
+            &Record::Put {
+                contact: contact("Jane"),
+            },
+        )
+        .unwrap();
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(journal_path(&path))
+            .unwrap();
+        journal
+            .write_all(&torn.as_bytes()[..torn.len() / 2])
+            .unwrap();
+
+        let mut storage = JournalStorage::open(&path, false).unwrap();
+        let mut contacts = storage.load().unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].first_name, "John");
+
+        contacts.push(contact("Anne"));
+        storage.put(&contacts[1]).unwrap();
+        storage.commit(&mut contacts.iter()).unwrap();
+
+        let mut storage = JournalStorage::open(&path, false).unwrap();
+        assert_eq!(names(&mut storage), ["Anne", "John"]);
+    }
+
+    #[test]
+    fn corrupt_final_record_is_ignored() {
+        let dir = ScratchDir::new("journal_corrupt_final");
+        let path = journal_with(&dir, &[&contact("John"), &contact("Jane")]);
+
+        let journal = fs::read_to_string(journal_path(&path)).unwrap();
+        let last = journal.trim_end().rfind('\n').unwrap() + 1;
+        let corrupted = format!(
+            "{}{}",
+            &journal[..last],
+            journal[last..].replace("Jane", "Joan")
+        );
+        fs::write(journal_path(&path), corrupted).unwrap();
+
+        let mut storage = JournalStorage::open(&path, false).unwrap();
+        assert_eq!(names(&mut storage), ["John"]);
+    }
+
+    #[test]
+    fn corrupt_record_before_valid_ones_is_an_error() {
+        let dir = ScratchDir::new("journal_corrupt_middle");
+        let path = journal_with(&dir, &[&contact("John"), &contact("Jane")]);
+
+        let journal = fs::read_to_string(journal_path(&path)).unwrap();
+        fs::write(journal_path(&path), journal.replacen("John", "Joan", 1)).unwrap();
+
+        match JournalStorage::open(&path, false) {
+            Err(PhoneBookError::Storage(reason)) => {
+                assert_eq!(reason, "journal record 1 is corrupt")
+            }
+            other => panic!("expected a corrupt journal error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn saving_a_snapshot_removes_the_journal() {
+        let dir = ScratchDir::new("journal_save_snapshot");
+        let john = contact("John");
+        let path = journal_with(&dir, &[&john]);
+        assert!(journal_path(&path).exists());
+
+        let contacts = load_snapshot(&path).unwrap();
+        assert_eq!(contacts[0].id, john.id);
+
+        save_snapshot(&path, false, &contacts).unwrap();
+        assert!(!journal_path(&path).exists());
+        assert_eq!(load_snapshot(&path).unwrap()[0].id, john.id);
+    }
+
+    #[test]
+    fn journal_from_an_older_snapshot_is_not_replayed() {
+        let dir = ScratchDir::new("journal_stale");
+        let (john, jane) = (contact("John"), contact("Jane"));
+        let path = journal_with(&dir, &[&john, &jane]);
+
+        // a crash between saving the snapshot and removing the journal leaves the journal behind
+        let journal = fs::read(journal_path(&path)).unwrap();
+        let mut contacts = load_snapshot(&path).unwrap();
+        contacts.retain(|contact| contact.id != john.id);
+        save_snapshot(&path, false, &contacts).unwrap();
+        fs::write(journal_path(&path), journal).unwrap();
+
+        let mut storage = JournalStorage::open(&path, false).unwrap();
+        let mut contacts = storage.load().unwrap();
+        assert_eq!(contacts[0].first_name, "Jane");
+
+        contacts.push(contact("Anne"));
+        storage.put(&contacts[1]).unwrap();
+        storage.commit(&mut contacts.iter()).unwrap();
+
+        let mut storage = JournalStorage::open(&path, false).unwrap();
+        assert_eq!(names(&mut storage), ["Anne", "Jane"]);
+    }
+}
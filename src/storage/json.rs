@@ -0,0 +1,76 @@
+use crate::storage::{self, Storage};
+use crate::{Contact, ContactId, Result};
+use std::path::{Path, PathBuf};
+
+/// Stores the whole phone book as a single json file in the same format as
+/// [`crate::PhoneBook::save_to_file`], the file is rewritten atomically from the phone book's
+/// contacts on commit. A journal left next to the file by [`crate::storage::JournalStorage`] is
+/// replayed on open and removed once the file is rewritten, since the file then holds every
+/// change in it
+#[derive(Debug)]
+pub struct JsonStorage {
+    path: PathBuf,
+    backup: bool,
+    /// Contacts read on open, handed over by the first `load` so they aren't kept twice
+    loaded: Option<Vec<Contact>>,
+    /// Set for new storage until the first commit writes its file
+    created: bool,
+    changed: bool,
+}
+
+impl JsonStorage {
+    pub fn open(path: &Path, backup: bool) -> Result<JsonStorage> {
+        Ok(JsonStorage {
+            path: path.to_path_buf(),
+            backup,
+            loaded: Some(storage::load_snapshot(path)?),
+            created: false,
+            changed: false,
+        })
+    }
+
+    /// An empty phone book, the file is not written until the first commit
+    pub fn create(path: &Path, backup: bool) -> JsonStorage {
+        JsonStorage {
+            path: path.to_path_buf(),
+            backup,
+            loaded: None,
+            created: true,
+            changed: true,
+        }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&mut self) -> Result<Vec<Contact>> {
+        match self.loaded.take() {
+            Some(contacts) => Ok(contacts),
+            None if self.created => Ok(Vec::new()),
+            None => storage::load_snapshot(&self.path),
+        }
+    }
+
+    fn put(&mut self, _contact: &Contact) -> Result<()> {
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn delete(&mut self, _id: ContactId) -> Result<()> {
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn commit(&mut self, contacts: &mut dyn Iterator<Item = &Contact>) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        storage::save_snapshot(&self.path, self.backup, contacts)?;
+        self.created = false;
+        self.changed = false;
+
+        Ok(())
+    }
+}
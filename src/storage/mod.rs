@@ -0,0 +1,180 @@
+mod journal;
+mod json;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use journal::JournalStorage;
+pub use json::JsonStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+use crate::{atomic, contact_id, Contact, ContactId, PhoneBookError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Somewhere contacts are persisted. A [`crate::PhoneBook`] opened on a storage loads every
+/// contact into memory and answers every lookup from its own indexes, the storage only persists
+/// the changes written through with `put` and `delete`. Nothing is guaranteed to be persisted
+/// until `commit` is called
+pub trait Storage: Debug {
+    /// Every contact as of the last commit
+    fn load(&mut self) -> Result<Vec<Contact>>;
+
+    /// Inserts the contact or replaces the stored contact with the same id
+    fn put(&mut self, contact: &Contact) -> Result<()>;
+
+    fn delete(&mut self, id: ContactId) -> Result<()>;
+
+    /// Makes every change since the storage was opened or last committed durable. `contacts` is
+    /// every contact once those changes are made, for storage that rewrites all of them rather
+    /// than only what changed
+    fn commit(&mut self, contacts: &mut dyn Iterator<Item = &Contact>) -> Result<()>;
+
+    /// Rewrites the stored data in its most compact form from `contacts`, storage that never
+    /// grows beyond its contents has nothing to do
+    fn compact(&mut self, _contacts: &mut dyn Iterator<Item = &Contact>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The whole phone book in a single json file, rewritten on every commit
+    Json,
+    /// A json snapshot with changes appended to a journal next to it until it is compacted
+    Journal,
+    /// An embedded SQLite database changed one row at a time, only available when built with the
+    /// sqlite feature
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = PhoneBookError;
+
+    fn from_str(s: &str) -> Result<Backend> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Backend::Json),
+            "journal" => Ok(Backend::Journal),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(PhoneBookError::UnsupportedStorage(format!(
+                "unknown storage backend {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Json => f.write_str("json"),
+            Backend::Journal => f.write_str("journal"),
+            Backend::Sqlite => f.write_str("sqlite"),
+        }
+    }
+}
+
+/// Which backend to use and where its data lives
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageLocation {
+    pub backend: Backend,
+    pub path: PathBuf,
+}
+
+impl StorageLocation {
+    /// Parses a location in the form `[backend:]path` such as `sqlite:contacts.db`. A backend
+    /// prefix takes precedence over `backend`, and a path without either is stored as json
+    pub fn parse(location: &str, backend: Option<Backend>) -> StorageLocation {
+        if let Some((prefix, path)) = location.split_once(':') {
+            if let Ok(backend) = prefix.parse() {
+                return StorageLocation {
+                    backend,
+                    path: PathBuf::from(path),
+                };
+            }
+        }
+
+        StorageLocation {
+            backend: backend.unwrap_or(Backend::Json),
+            path: PathBuf::from(location),
+        }
+    }
+
+    /// Opens existing storage, `backup` keeps the previous version of json files when saving
+    pub fn open(&self, backup: bool) -> Result<Box<dyn Storage>> {
+        match self.backend {
+            Backend::Json => Ok(Box::new(JsonStorage::open(&self.path, backup)?)),
+            Backend::Journal => Ok(Box::new(JournalStorage::open(&self.path, backup)?)),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => Ok(Box::new(SqliteStorage::open(&self.path)?)),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Sqlite => Err(sqlite_unsupported()),
+        }
+    }
+
+    /// Creates empty storage, replacing anything already at the location once committed
+    pub fn create(&self, backup: bool) -> Result<Box<dyn Storage>> {
+        match self.backend {
+            Backend::Json => Ok(Box::new(JsonStorage::create(&self.path, backup))),
+            Backend::Journal => Ok(Box::new(JournalStorage::create(&self.path, backup))),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => Ok(Box::new(SqliteStorage::create(&self.path)?)),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Sqlite => Err(sqlite_unsupported()),
+        }
+    }
+}
+
+/// Every contact in the snapshot at `path` with its journal replayed over it
+pub(crate) fn load_snapshot(path: &Path) -> Result<Vec<Contact>> {
+    Ok(journal::read(path)?.0.into_values().collect())
+}
+
+/// A phone book as it is stored on disk by the json and journal backends, written with
+/// `&Contact` and read back with `Contact`. The indexes are rebuilt from this on load
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Snapshot<C> {
+    /// Matched against the journal's records before they are replayed, files written without
+    /// one are generation 0
+    #[serde(default)]
+    pub(crate) generation: u64,
+    pub(crate) contacts: Vec<C>,
+}
+
+/// Atomically replaces the snapshot at `path` with `contacts` under a new generation and removes
+/// its journal, returning the generation
+pub(crate) fn save_snapshot<'a>(
+    path: &Path,
+    backup: bool,
+    contacts: impl IntoIterator<Item = &'a Contact>,
+) -> Result<u64> {
+    let snapshot = Snapshot {
+        generation: new_generation(),
+        contacts: contacts.into_iter().collect::<Vec<_>>(),
+    };
+    atomic::write_file(path, backup, |writer| {
+        serde_json::to_writer_pretty(writer, &snapshot)?;
+        Ok(())
+    })?;
+
+    // the journal belongs to the previous generation now, so if it can't be removed it is only
+    // ignored on the next load rather than replayed
+    let _ = journal::remove_journal(path);
+
+    Ok(snapshot.generation)
+}
+
+/// A random generation that isn't 0, so it can't be mistaken for a file without one
+fn new_generation() -> u64 {
+    (contact_id::random_bits() as u64).max(1)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_unsupported() -> PhoneBookError {
+    PhoneBookError::UnsupportedStorage(
+        "sqlite storage requires building with the sqlite feature".to_string(),
+    )
+}
@@ -0,0 +1,265 @@
+use crate::storage::Storage;
+use crate::{Contact, ContactId, Result};
+use rusqlite::{params, Connection, OpenFlags};
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS contacts (
+        id TEXT PRIMARY KEY NOT NULL,
+        first_name TEXT NOT NULL,
+        last_name TEXT NOT NULL,
+        city TEXT,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS phone_numbers (
+        number TEXT PRIMARY KEY NOT NULL,
+        contact_id TEXT NOT NULL REFERENCES contacts (id)
+    );
+    CREATE INDEX IF NOT EXISTS contacts_first_name ON contacts (first_name);
+    CREATE INDEX IF NOT EXISTS contacts_last_name ON contacts (last_name);
+    CREATE INDEX IF NOT EXISTS contacts_city ON contacts (city);
+    CREATE INDEX IF NOT EXISTS phone_numbers_contact_id ON phone_numbers (contact_id);
+";
+
+/// Stores contacts in an embedded SQLite database, one row per contact so a change only writes
+/// the contacts it touches. Each contact is kept whole as json, with its names, city and phone
+/// numbers also in indexed columns for anything reading the database directly, and each phone
+/// number is the key of its own row so the database rejects a number used twice. Changes are
+/// made inside a transaction which is committed on `commit`, closing the storage rolls back
+/// anything that was not committed
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<SqliteStorage> {
+        SqliteStorage::connect(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+    }
+
+    /// Creates the database if needed and removes any contacts already in it
+    pub fn create(path: &Path) -> Result<SqliteStorage> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let storage = SqliteStorage::connect(path, flags)?;
+        storage
+            .connection
+            .execute_batch("DELETE FROM phone_numbers; DELETE FROM contacts;")?;
+
+        Ok(storage)
+    }
+
+    fn connect(path: &Path, flags: OpenFlags) -> Result<SqliteStorage> {
+        let connection = Connection::open_with_flags(path, flags)?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch("BEGIN")?;
+
+        Ok(SqliteStorage { connection })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Vec<Contact>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT data FROM contacts")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut contacts = Vec::new();
+        for data in rows {
+            contacts.push(serde_json::from_str(&data?)?);
+        }
+
+        Ok(contacts)
+    }
+
+    fn put(&mut self, contact: &Contact) -> Result<()> {
+        let id = contact.id.to_string();
+        let city = contact.address.as_ref().map(|address| &address.city);
+
+        self.delete(contact.id)?;
+        self.connection
+            .prepare_cached(
+                "INSERT INTO contacts (id, first_name, last_name, city, data) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                id,
+                contact.first_name,
+                contact.last_name,
+                city,
+                serde_json::to_string(contact)?,
+            ])?;
+
+        let mut insert_number = self
+            .connection
+            .prepare_cached("INSERT INTO phone_numbers (number, contact_id) VALUES (?1, ?2)")?;
+        for number in contact.numbers() {
+            insert_number.execute(params![number.as_str(), id])?;
+        }
+
+        Ok(())
+    }
+
+    fn delete(&mut self, id: ContactId) -> Result<()> {
+        let id = id.to_string();
+
+        self.connection
+            .prepare_cached("DELETE FROM phone_numbers WHERE contact_id = ?1")?
+            .execute(params![id])?;
+        self.connection
+            .prepare_cached("DELETE FROM contacts WHERE id = ?1")?
+            .execute(params![id])?;
+
+        Ok(())
+    }
+
+    fn commit(&mut self, _contacts: &mut dyn Iterator<Item = &Contact>) -> Result<()> {
+        self.connection.execute_batch("COMMIT; BEGIN")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::ScratchDir;
+    use crate::{Address, LabelledNumber, PhoneLabel, PhoneNumber, Region};
+
+    fn contact(first_name: &str, number: &str, city: &str) -> Contact {
+        let number = PhoneNumber::parse(number, Region::default_region()).unwrap();
+        let address = Address {
+            city: city.to_string(),
+            ..Address::default()
+        };
+
+        Contact::new(
+            first_name.to_string(),
+            "Smith".to_string(),
+            vec![LabelledNumber {
+                label: PhoneLabel::Mobile,
+                number,
+                primary: true,
+            }],
+            Some(address),
+        )
+    }
+
+    fn names(contacts: Vec<Contact>) -> Vec<String> {
+        let mut names = contacts
+            .into_iter()
+            .map(|contact| contact.first_name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn committed_changes_survive_reopening() {
+        let dir = ScratchDir::new("sqlite_round_trip");
+        let path = dir.join("contacts.db");
+        let john = contact("John", "5551234567", "Perth");
+        let jane = contact("Jane", "5559876543", "Sydney");
+
+        let mut storage = SqliteStorage::create(&path).unwrap();
+        storage.put(&john).unwrap();
+        storage.put(&jane).unwrap();
+        storage.commit(&mut std::iter::empty()).unwrap();
+
+        let mut renamed = john.clone();
+        renamed.first_name = "Jack".to_string();
+        storage.put(&renamed).unwrap();
+        storage.delete(jane.id).unwrap();
+        storage.commit(&mut std::iter::empty()).unwrap();
+        drop(storage);
+
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        let contacts = storage.load().unwrap();
+        assert_eq!(names(contacts.clone()), ["Jack"]);
+        assert_eq!(contacts[0].id, john.id);
+    }
+
+    #[test]
+    fn uncommitted_changes_are_rolled_back() {
+        let dir = ScratchDir::new("sqlite_rolled_back");
+        let path = dir.join("contacts.db");
+        let mut storage = SqliteStorage::create(&path).unwrap();
+        storage
+            .put(&contact("John", "5551234567", "Perth"))
+            .unwrap();
+        storage.commit(&mut std::iter::empty()).unwrap();
+
+        storage
+            .put(&contact("Jane", "5559876543", "Sydney"))
+            .unwrap();
+        drop(storage);
+
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(names(storage.load().unwrap()), ["John"]);
+    }
+
+    #[test]
+    fn contacts_are_kept_in_their_columns() {
+        let dir = ScratchDir::new("sqlite_columns");
+        let path = dir.join("contacts.db");
+        let john = contact("John", "5551234567", "Perth");
+        let mut storage = SqliteStorage::create(&path).unwrap();
+        storage.put(&john).unwrap();
+
+        let (first_name, city, number) = storage
+            .connection
+            .query_row(
+                "SELECT first_name, city, number FROM contacts \
+                 JOIN phone_numbers ON phone_numbers.contact_id = contacts.id",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!((first_name.as_str(), city.as_str()), ("John", "Perth"));
+        assert_eq!(number, john.numbers().next().unwrap().as_str());
+    }
+
+    #[test]
+    fn replacing_a_contact_replaces_its_numbers() {
+        let dir = ScratchDir::new("sqlite_replace_numbers");
+        let path = dir.join("contacts.db");
+        let john = contact("John", "5551234567", "Perth");
+        let mut storage = SqliteStorage::create(&path).unwrap();
+        storage.put(&john).unwrap();
+
+        let mut changed = john.clone();
+        changed.phone_numbers[0].number =
+            PhoneNumber::parse("5550001111", Region::default_region()).unwrap();
+        storage.put(&changed).unwrap();
+
+        let numbers = storage
+            .connection
+            .query_row(
+                "SELECT group_concat(number) FROM phone_numbers",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap();
+        assert_eq!(numbers, changed.numbers().next().unwrap().as_str());
+    }
+
+    #[test]
+    fn a_number_used_by_another_contact_is_rejected() {
+        let dir = ScratchDir::new("sqlite_duplicate_number");
+        let path = dir.join("contacts.db");
+        let mut storage = SqliteStorage::create(&path).unwrap();
+        storage
+            .put(&contact("John", "5551234567", "Perth"))
+            .unwrap();
+
+        assert!(storage
+            .put(&contact("Jane", "5551234567", "Sydney"))
+            .is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Finding names typed on a phone keypad, where each digit stands for the letters printed on its
+//! key (2 for ABC up to 9 for WXYZ) and 0 for a space, so 76484 finds "Smith" and 5646076484
+//! finds "John Smith"
+
+use crate::trie::DigitTrie;
+use crate::{Contact, ContactId, MatchPolicy, PhoneBookError, Result};
+use std::collections::HashSet;
+
+/// Maps the keypad digits of every contact's first name, last name and full name to the ids of
+/// the contacts with those names
+#[derive(Debug, Default)]
+pub(crate) struct T9Index {
+    names: DigitTrie<HashSet<ContactId>>,
+}
+
+impl T9Index {
+    pub(crate) fn insert(&mut self, contact: &Contact) {
+        for key in keys(contact) {
+            match self.names.get_mut(&key) {
+                Some(ids) => {
+                    ids.insert(contact.id);
+                }
+                None => {
+                    // keys are made of digits so the trie always accepts them
+                    let _ = self.names.insert(&key, HashSet::from([contact.id]));
+                }
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, contact: &Contact) {
+        for key in keys(contact) {
+            if let Some(ids) = self.names.get_mut(&key) {
+                ids.remove(&contact.id);
+
+                if ids.is_empty() {
+                    self.names.remove(&key);
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Ids of every contact with a name starting with letters typed as `digits`
+    pub(crate) fn prefix(&self, digits: &str) -> Result<HashSet<&ContactId>> {
+        let invalid = |reason| PhoneBookError::InvalidKeypadDigits {
+            digits: digits.to_string(),
+            reason,
+        };
+
+        if digits.is_empty() {
+            return Err(invalid("at least one digit must be given"));
+        }
+        if !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid("only the digits 0 to 9 can be typed"));
+        }
+
+        Ok(self.names.prefix(digits).into_iter().flatten().collect())
+    }
+}
+
+/// The keypad digits for a contact's first name, last name and the two together
+fn keys(contact: &Contact) -> HashSet<String> {
+    let full_name = format!("{} {}", contact.first_name, contact.last_name);
+
+    [&contact.first_name, &contact.last_name, &full_name]
+        .into_iter()
+        .map(|name| digits(name))
+        .filter(|key| !key.is_empty())
+        .collect()
+}
+
+/// The digits typed for a name once accents are removed, characters without a key such as
+/// apostrophes and hyphens are skipped
+fn digits(name: &str) -> String {
+    MatchPolicy::Normalized
+        .key(name)
+        .chars()
+        .filter_map(|c| {
+            let digit = match c {
+                'a'..='c' => '2',
+                'd'..='f' => '3',
+                'g'..='i' => '4',
+                'j'..='l' => '5',
+                'm'..='o' => '6',
+                'p'..='s' => '7',
+                't'..='v' => '8',
+                'w'..='z' => '9',
+                ' ' => '0',
+                '0'..='9' => c,
+                _ => return None,
+            };
+            Some(digit)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LabelledNumber, PhoneLabel};
+
+    fn contact(first_name: &str, last_name: &str, number: &str) -> Contact {
+        let number = LabelledNumber {
+            label: PhoneLabel::Mobile,
+            number: number.parse().unwrap(),
+            primary: true,
+        };
+
+        Contact::new(
+            first_name.to_string(),
+            last_name.to_string(),
+            vec![number],
+            None,
+        )
+    }
+
+    #[test]
+    fn removing_every_contact_leaves_no_keys() {
+        let john = contact("John", "Smith", "5550000001");
+        let jane = contact("Jane", "Smith", "5550000002");
+        let mut index = T9Index::default();
+        index.insert(&john);
+        index.insert(&jane);
+
+        index.remove(&john);
+        assert_eq!(index.prefix("76484").unwrap(), HashSet::from([&jane.id]));
+        assert!(index.prefix("5646").unwrap().is_empty());
+
+        index.remove(&jane);
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn letters_are_typed_on_their_keys() {
+        assert_eq!(
+            digits("abcdefghijklmnopqrstuvwxyz"),
+            "22233344455566677778889999"
+        );
+        assert_eq!(digits("SMITH"), "76484");
+        assert_eq!(digits("O'Brien-Smith"), "62743676484");
+    }
+
+    #[test]
+    fn zero_is_a_space() {
+        assert_eq!(digits("John Smith"), "5646076484");
+
+        let john = contact("John", "Smith", "5550000001");
+        let mut index = T9Index::default();
+        index.insert(&john);
+        assert_eq!(index.prefix("56460764").unwrap(), HashSet::from([&john.id]));
+        assert!(index.prefix("5646076485").unwrap().is_empty());
+    }
+
+    #[test]
+    fn accents_are_folded_before_typing() {
+        assert_eq!(digits("Zoë"), "963");
+        assert_eq!(digits("Ångström"), digits("Angstrom"));
+        assert_eq!(digits("Müller"), "685537");
+    }
+
+    #[test]
+    fn only_digits_can_be_typed() {
+        let index = T9Index::default();
+
+        assert!(matches!(
+            index.prefix(""),
+            Err(PhoneBookError::InvalidKeypadDigits { .. })
+        ));
+        assert!(matches!(
+            index.prefix("76a"),
+            Err(PhoneBookError::InvalidKeypadDigits { .. })
+        ));
+    }
+}
@@ -0,0 +1,41 @@
+//! Fixtures shared by the tests of several modules
+
+use crate::{LabelledNumber, PhoneLabel, PhoneNumber, Region};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+/// A labelled number read in the default region
+pub(crate) fn labelled(number: &str, label: PhoneLabel, primary: bool) -> LabelledNumber {
+    LabelledNumber {
+        label,
+        number: PhoneNumber::parse(number, Region::default_region()).unwrap(),
+        primary,
+    }
+}
+
+/// A fresh directory for a test to keep its files in, removed again when dropped
+pub(crate) struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    pub(crate) fn new(name: &str) -> ScratchDir {
+        let dir = env::temp_dir().join(format!("phone_book_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        ScratchDir(dir)
+    }
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,174 @@
+/// A trie keyed on strings of ascii digits, used to answer phone number and keypad prefix queries
+/// without scanning every contact
+#[derive(Debug)]
+pub(crate) struct DigitTrie<V> {
+    root: Node<V>,
+}
+
+#[derive(Debug)]
+struct Node<V> {
+    value: Option<V>,
+    children: [Option<Box<Node<V>>>; 10],
+}
+
+impl<V> Node<V> {
+    fn new() -> Node<V> {
+        Node {
+            value: None,
+            children: Default::default(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.value.is_none() && self.children.iter().all(Option::is_none)
+    }
+
+    fn collect<'a>(&'a self, values: &mut Vec<&'a V>) {
+        if let Some(value) = &self.value {
+            values.push(value);
+        }
+
+        for child in self.children.iter().flatten() {
+            child.collect(values);
+        }
+    }
+
+    /// Removes the value stored under `digits` and prunes any nodes left without values or
+    /// children on the way back up
+    fn remove(&mut self, digits: &[usize]) -> Option<V> {
+        let (first, rest) = match digits.split_first() {
+            Some(split) => split,
+            None => return self.value.take(),
+        };
+
+        let child = self.children[*first].as_mut()?;
+        let value = child.remove(rest);
+
+        if child.is_empty() {
+            self.children[*first] = None;
+        }
+
+        value
+    }
+}
+
+impl<V> Default for DigitTrie<V> {
+    fn default() -> DigitTrie<V> {
+        DigitTrie { root: Node::new() }
+    }
+}
+
+impl<V> DigitTrie<V> {
+    /// Stores `value` under `key`, returning the value previously stored there. Keys containing
+    /// anything other than ascii digits are rejected and handed back as an error
+    pub(crate) fn insert(&mut self, key: &str, value: V) -> Result<Option<V>, V> {
+        let digits = match digits(key) {
+            Some(digits) => digits,
+            None => return Err(value),
+        };
+
+        let mut node = &mut self.root;
+        for digit in digits {
+            node = node.children[digit].get_or_insert_with(|| Box::new(Node::new()));
+        }
+
+        Ok(node.value.replace(value))
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<V> {
+        self.root.remove(&digits(key)?)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&V> {
+        self.node(key)?.value.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let mut node = &mut self.root;
+        for digit in digits(key)? {
+            node = node.children[digit].as_deref_mut()?;
+        }
+
+        node.value.as_mut()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    /// Every value whose key starts with `prefix`, ordered by key
+    pub(crate) fn prefix(&self, prefix: &str) -> Vec<&V> {
+        let mut values = Vec::new();
+
+        if let Some(node) = self.node(prefix) {
+            node.collect(&mut values);
+        }
+
+        values
+    }
+
+    fn node(&self, key: &str) -> Option<&Node<V>> {
+        let mut node = &self.root;
+        for digit in digits(key)? {
+            node = node.children[digit].as_deref()?;
+        }
+
+        Some(node)
+    }
+}
+
+fn digits(key: &str) -> Option<Vec<usize>> {
+    key.chars()
+        .map(|c| c.to_digit(10).map(|digit| digit as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_digit_keys_are_accepted() {
+        let mut trie = DigitTrie::default();
+
+        assert_eq!(trie.insert("123", 'a'), Ok(None));
+        assert_eq!(trie.insert("123", 'b'), Ok(Some('a')));
+        assert_eq!(trie.insert("12a", 'c'), Err('c'));
+        assert_eq!(trie.get("123"), Some(&'b'));
+        assert_eq!(trie.get("12"), None);
+    }
+
+    #[test]
+    fn prefix_returns_values_ordered_by_key() {
+        let mut trie = DigitTrie::default();
+        for (key, value) in [("129", 'c'), ("12", 'a'), ("1234", 'b'), ("13", 'd')] {
+            trie.insert(key, value).unwrap();
+        }
+
+        assert_eq!(trie.prefix("12"), [&'a', &'b', &'c']);
+        assert_eq!(trie.prefix(""), [&'a', &'b', &'c', &'d']);
+        assert!(trie.prefix("14").is_empty());
+    }
+
+    #[test]
+    fn remove_prunes_nodes_left_empty() {
+        let mut trie = DigitTrie::default();
+        trie.insert("12", 'a').unwrap();
+        trie.insert("1234", 'b').unwrap();
+        trie.insert("1256", 'c').unwrap();
+
+        assert_eq!(trie.remove("1234"), Some('b'));
+        assert!(trie.node("123").is_none());
+        assert!(trie.node("125").is_some());
+
+        // the value at 12 keeps its node after the nodes below it are pruned
+        assert_eq!(trie.remove("1256"), Some('c'));
+        assert!(trie.node("125").is_none());
+        assert_eq!(trie.get("12"), Some(&'a'));
+
+        assert_eq!(trie.remove("12"), Some('a'));
+        assert!(trie.is_empty());
+        assert_eq!(trie.remove("12"), None);
+    }
+}
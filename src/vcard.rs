@@ -0,0 +1,671 @@
+//! Reading and writing contacts as vCards ([RFC 2426] for version 3.0 and [RFC 6350] for 4.0),
+//! the format used by most phones and address books to exchange contacts. Names are mapped to
+//! `FN` and `N`, phone numbers to `TEL` and the address to `ADR`, anything else on a card is
+//! ignored when reading.
+//!
+//! [RFC 2426]: https://www.rfc-editor.org/rfc/rfc2426
+//! [RFC 6350]: https://www.rfc-editor.org/rfc/rfc6350
+
+use crate::{
+    Address, Contact, ContactId, LabelledNumber, PhoneBookError, PhoneLabel, PhoneNumber, Region,
+    Result,
+};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+/// Longest line in octets before it has to be folded onto a continuation line
+const MAX_LINE_LENGTH: usize = 75;
+
+/// `TYPE` values describing what a number can do rather than what it is for, they don't make a
+/// useful label on their own
+const IGNORED_TYPES: [&str; 6] = ["pref", "voice", "text", "msg", "video", "internet"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VCardVersion {
+    V3,
+    V4,
+}
+
+impl Display for VCardVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VCardVersion::V3 => f.write_str("3.0"),
+            VCardVersion::V4 => f.write_str("4.0"),
+        }
+    }
+}
+
+/// A card read from a vCard file
+#[derive(Debug)]
+pub struct Card {
+    /// Line the card starts on
+    pub line: usize,
+    /// The card's formatted name, empty if it doesn't have one
+    pub name: String,
+    /// The contact on the card or why it couldn't be read, the contact has not been validated
+    pub contact: Result<Contact>,
+}
+
+/// A property from a card, `name` and parameter names are upper case and parameter values have
+/// their quotes removed
+struct Property {
+    line: usize,
+    name: String,
+    parameters: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: usize, text: &str) -> Result<Property> {
+        let head = split_unquoted(text, ':')[0];
+        let value = text
+            .get(head.len() + 1..)
+            .ok_or_else(|| invalid(line, "property has no value"))?;
+
+        let mut head = split_unquoted(head, ';').into_iter();
+        let name = head.next().unwrap_or_default();
+        // a group prefix such as item1.TEL only ties properties together, it doesn't change them
+        let name = name.rsplit('.').next().unwrap_or(name).trim();
+
+        let mut parameters = Vec::new();
+        for parameter in head {
+            // vCard 2.1 allows a bare type such as TEL;CELL
+            let (key, values) = parameter.split_once('=').unwrap_or(("TYPE", parameter));
+            for value in split_unquoted(values, ',') {
+                parameters.push((key.trim().to_uppercase(), value.replace('"', "")));
+            }
+        }
+
+        Ok(Property {
+            line,
+            name: name.to_uppercase(),
+            parameters,
+            value: value.to_string(),
+        })
+    }
+
+    /// Values of the `TYPE` parameter in lower case
+    fn types(&self) -> Vec<String> {
+        self.parameters
+            .iter()
+            .filter(|(key, _)| key == "TYPE")
+            .map(|(_, value)| value.trim().to_lowercase())
+            .collect()
+    }
+
+    fn preferred(&self) -> bool {
+        self.types().iter().any(|value| value == "pref")
+            || self.parameters.iter().any(|(key, _)| key == "PREF")
+    }
+}
+
+/// A card while its properties are being read, only the first error on a card is kept
+struct PartialCard {
+    line: usize,
+    properties: Vec<Property>,
+    error: Option<PhoneBookError>,
+}
+
+impl PartialCard {
+    fn finish(self, region: &'static Region) -> Card {
+        let name = self
+            .properties
+            .iter()
+            .find(|property| property.name == "FN")
+            .map(|property| unescape(&property.value))
+            .unwrap_or_default();
+
+        let contact = match self.error {
+            Some(error) => Err(error),
+            None => to_contact(self.line, &self.properties, region),
+        };
+
+        Card {
+            line: self.line,
+            name,
+            contact,
+        }
+    }
+}
+
+/// Reads every card in `input`, a card that can't be turned into a contact is returned with the
+/// reason rather than failing the whole file. Only text outside of any card is an error. Numbers
+/// without a country calling code are read as national numbers of `region`
+pub fn read(input: &str, region: &'static Region) -> Result<Vec<Card>> {
+    let mut cards = Vec::new();
+    let mut current: Option<PartialCard> = None;
+
+    for (line, text) in unfold(input) {
+        let text = text.trim_end();
+
+        if text.eq_ignore_ascii_case("BEGIN:VCARD") {
+            if let Some(mut card) = current.take() {
+                card.error
+                    .get_or_insert(invalid(card.line, "card has no END:VCARD"));
+                cards.push(card.finish(region));
+            }
+
+            current = Some(PartialCard {
+                line,
+                properties: Vec::new(),
+                error: None,
+            });
+        } else if text.eq_ignore_ascii_case("END:VCARD") {
+            let card = current
+                .take()
+                .ok_or_else(|| invalid(line, "END:VCARD without BEGIN:VCARD"))?;
+            cards.push(card.finish(region));
+        } else {
+            let card = current
+                .as_mut()
+                .ok_or_else(|| invalid(line, "expected BEGIN:VCARD"))?;
+
+            match Property::parse(line, text) {
+                Ok(property) => card.properties.push(property),
+                Err(error) => {
+                    card.error.get_or_insert(error);
+                }
+            }
+        }
+    }
+
+    if let Some(mut card) = current {
+        card.error
+            .get_or_insert(invalid(card.line, "card has no END:VCARD"));
+        cards.push(card.finish(region));
+    }
+
+    Ok(cards)
+}
+
+/// Writes each contact as a card, lines are folded and end with CRLF as the format requires
+pub fn write<'a, W: Write>(
+    mut writer: W,
+    contacts: impl IntoIterator<Item = &'a Contact>,
+    version: VCardVersion,
+) -> Result<()> {
+    for contact in contacts {
+        for line in to_lines(contact, version) {
+            write_folded(&mut writer, &line)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn to_contact(line: usize, properties: &[Property], region: &'static Region) -> Result<Contact> {
+    let find = |name| {
+        properties
+            .iter()
+            .filter(move |property| property.name == name)
+    };
+
+    if let Some(version) = find("VERSION").next() {
+        if !["2.1", "3.0", "4.0"].contains(&version.value.trim()) {
+            return Err(invalid(
+                version.line,
+                format!("unsupported version {:?}", version.value),
+            ));
+        }
+    }
+
+    let (mut first_name, mut last_name) = match find("N").next() {
+        Some(name) => (
+            structured(&name.value, 1, " "),
+            structured(&name.value, 0, " "),
+        ),
+        None => (String::new(), String::new()),
+    };
+
+    // N is required by 3.0 but not 4.0, fall back to splitting the formatted name
+    if first_name.is_empty() && last_name.is_empty() {
+        let formatted = find("FN")
+            .next()
+            .map(|name| unescape(&name.value))
+            .unwrap_or_default();
+        let formatted = formatted.trim();
+
+        match formatted.rsplit_once(char::is_whitespace) {
+            Some((first, last)) => {
+                first_name = first.trim().to_string();
+                last_name = last.to_string();
+            }
+            None => first_name = formatted.to_string(),
+        }
+    }
+
+    if first_name.is_empty() && last_name.is_empty() {
+        return Err(invalid(line, "card has no name"));
+    }
+
+    let mut phone_numbers = Vec::new();
+    let mut primary = None;
+    for tel in find("TEL") {
+        let number = tel.value.trim();
+        let number = match number.get(..4) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("tel:") => &number[4..],
+            _ => number,
+        };
+        // tel URIs can carry parameters such as ;ext=123 after the number
+        let number = number.split(';').next().unwrap_or_default();
+
+        if tel.preferred() && primary.is_none() {
+            primary = Some(phone_numbers.len());
+        }
+
+        phone_numbers.push(LabelledNumber {
+            label: label(&tel.types()),
+            number: PhoneNumber::parse(number, region)?,
+            primary: false,
+        });
+    }
+
+    if let Some(number) = phone_numbers.get_mut(primary.unwrap_or_default()) {
+        number.primary = true;
+    }
+
+    let address = find("ADR")
+        .find(|adr| adr.preferred())
+        .or_else(|| find("ADR").next())
+        .map(|adr| Address {
+            street_address: structured(&adr.value, 2, ", "),
+            city: structured(&adr.value, 3, ", "),
+            state: structured(&adr.value, 4, ", "),
+            postcode: structured(&adr.value, 5, ", "),
+            country: structured(&adr.value, 6, ", "),
+        });
+
+    let id = find("UID")
+        .next()
+        .and_then(|uid| uid.value.trim().parse().ok())
+        .unwrap_or_else(ContactId::generate);
+
+    Ok(Contact {
+        id,
+        first_name,
+        last_name,
+        phone_numbers,
+        address,
+    })
+}
+
+fn label(types: &[String]) -> PhoneLabel {
+    let has = |value: &str| types.iter().any(|t| t == value);
+
+    if has("fax") {
+        PhoneLabel::Fax
+    } else if has("cell") || has("mobile") {
+        PhoneLabel::Mobile
+    } else if let Some(custom) = types.iter().find(|t| {
+        !IGNORED_TYPES.contains(&t.as_str()) && t.as_str() != "work" && t.as_str() != "home"
+    }) {
+        PhoneLabel::from(custom.clone())
+    } else if has("work") {
+        PhoneLabel::Work
+    } else if has("home") {
+        PhoneLabel::Home
+    } else {
+        PhoneLabel::Mobile
+    }
+}
+
+fn to_lines(contact: &Contact, version: VCardVersion) -> Vec<String> {
+    let formatted = [contact.first_name.as_str(), contact.last_name.as_str()]
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut lines = vec![
+        "BEGIN:VCARD".to_string(),
+        format!("VERSION:{}", version),
+        format!("UID:{}", contact.id),
+        format!("FN:{}", escape(&formatted)),
+        format!(
+            "N:{};{};;;",
+            escape(&contact.last_name),
+            escape(&contact.first_name)
+        ),
+    ];
+
+    for number in &contact.phone_numbers {
+        let label = match &number.label {
+            PhoneLabel::Mobile => "cell".to_string(),
+            PhoneLabel::Custom(label) => parameter_value(label),
+            label => label.to_string(),
+        };
+
+        lines.push(match version {
+            VCardVersion::V3 if number.primary => {
+                format!("TEL;TYPE={},PREF:{}", label.to_uppercase(), number.number)
+            }
+            VCardVersion::V3 => format!("TEL;TYPE={}:{}", label.to_uppercase(), number.number),
+            VCardVersion::V4 if number.primary => {
+                format!("TEL;VALUE=uri;TYPE={};PREF=1:tel:{}", label, number.number)
+            }
+            VCardVersion::V4 => format!("TEL;VALUE=uri;TYPE={}:tel:{}", label, number.number),
+        });
+    }
+
+    if let Some(address) = &contact.address {
+        lines.push(format!(
+            "ADR:;;{};{};{};{};{}",
+            escape(&address.street_address),
+            escape(&address.city),
+            escape(&address.state),
+            escape(&address.postcode),
+            escape(&address.country)
+        ));
+    }
+
+    lines.push("END:VCARD".to_string());
+    lines
+}
+
+/// Joins continuation lines, which start with a space or tab, onto the line before them and
+/// pairs each logical line with the line number it started on
+fn unfold(input: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+
+    for (index, line) in input.lines().enumerate() {
+        if let (Some(continuation), Some((_, last))) =
+            (line.strip_prefix([' ', '\t']), lines.last_mut())
+        {
+            last.push_str(continuation);
+        } else if !line.trim().is_empty() {
+            lines.push((index + 1, line.to_string()));
+        }
+    }
+
+    lines
+}
+
+/// Writes the line split into chunks of at most [`MAX_LINE_LENGTH`] octets, never splitting a
+/// character
+fn write_folded<W: Write>(writer: &mut W, line: &str) -> Result<()> {
+    let mut start = 0;
+    // continuation lines lose an octet to the leading space
+    let mut limit = MAX_LINE_LENGTH;
+
+    while line.len() - start > limit {
+        let mut end = start + limit;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        writer.write_all(&line.as_bytes()[start..end])?;
+        writer.write_all(b"\r\n ")?;
+        start = end;
+        limit = MAX_LINE_LENGTH - 1;
+    }
+
+    writer.write_all(&line.as_bytes()[start..])?;
+    writer.write_all(b"\r\n")?;
+
+    Ok(())
+}
+
+/// Splits on `separator` wherever it is outside double quotes
+fn split_unquoted(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (index, c) in s.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(&s[start..index]);
+            start = index + c.len_utf8();
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+/// Splits on `separator` wherever it isn't escaped with a backslash
+fn split_escaped(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (index, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == separator {
+            parts.push(&s[start..index]);
+            start = index + c.len_utf8();
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+/// Component `index` of a structured value such as `N` or `ADR`, a component holding several
+/// values is joined with `joiner`
+fn structured(value: &str, index: usize, joiner: &str) -> String {
+    split_escaped(value, ';')
+        .get(index)
+        .map(|component| {
+            split_escaped(component, ',')
+                .into_iter()
+                .map(|value| unescape(value.trim()))
+                .filter(|value| !value.is_empty())
+                .collect::<Vec<_>>()
+                .join(joiner)
+        })
+        .unwrap_or_default()
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\\' | ',' | ';' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Parameter values can't be escaped, they are quoted instead when they hold a separator
+fn parameter_value(s: &str) -> String {
+    let s = s.replace('"', "");
+
+    if s.contains([',', ';', ':']) {
+        format!("\"{}\"", s)
+    } else {
+        s
+    }
+}
+
+fn invalid(line: usize, reason: impl Into<String>) -> PhoneBookError {
+    PhoneBookError::InvalidVCard {
+        line,
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::labelled;
+
+    fn folded(line: &str) -> String {
+        let mut output = Vec::new();
+        write_folded(&mut output, line).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn contact() -> Contact {
+        Contact::new(
+            "Mary-Jane; \"MJ\"".to_string(),
+            "O'Brien, Esq.".to_string(),
+            vec![
+                labelled("2025550101", PhoneLabel::Work, false),
+                labelled("+61 412 345 678", PhoneLabel::Mobile, true),
+                labelled(
+                    "2025550102",
+                    PhoneLabel::from("boat; shed".to_string()),
+                    false,
+                ),
+            ],
+            Some(Address {
+                street_address: "Unit 4\n12 Long Street, Back\\Lane".to_string(),
+                city: "Ōtautahi".to_string(),
+                state: String::new(),
+                postcode: "8011".to_string(),
+                country: "New Zealand; Aotearoa".to_string(),
+            }),
+        )
+    }
+
+    fn round_trip(contact: &Contact, version: VCardVersion) -> Contact {
+        let mut output = Vec::new();
+        write(&mut output, [contact], version).unwrap();
+
+        let mut cards = read(
+            &String::from_utf8(output).unwrap(),
+            Region::default_region(),
+        )
+        .unwrap();
+        assert_eq!(cards.len(), 1);
+        cards.remove(0).contact.unwrap()
+    }
+
+    #[test]
+    fn long_lines_are_folded_without_splitting_characters() {
+        let line = format!("NOTE:{}", "é".repeat(100));
+        let output = folded(&line);
+
+        assert!(output.ends_with("\r\n"));
+        for physical in output.split_terminator("\r\n") {
+            assert!(physical.len() <= MAX_LINE_LENGTH, "{:?}", physical);
+        }
+        assert_eq!(unfold(&output), [(1, line)]);
+    }
+
+    #[test]
+    fn short_lines_are_not_folded() {
+        let line = "N".repeat(MAX_LINE_LENGTH);
+        assert_eq!(folded(&line), format!("{}\r\n", line));
+        assert_eq!(folded(&format!("{}X", line)).matches("\r\n").count(), 2);
+    }
+
+    #[test]
+    fn unfold_joins_continuations_and_keeps_starting_lines() {
+        let input = "BEGIN:VCARD\r\nFN:John\r\n  Smith\r\n\r\nNOTE:a\r\n\tb\r\nEND:VCARD\r\n";
+
+        assert_eq!(
+            unfold(input),
+            [
+                (1, "BEGIN:VCARD".to_string()),
+                (2, "FN:John Smith".to_string()),
+                (5, "NOTE:ab".to_string()),
+                (7, "END:VCARD".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        for s in [
+            "plain",
+            "a,b;c\\d",
+            "two\nlines",
+            "trailing\\",
+            "",
+            "ünï;cödé",
+        ] {
+            assert_eq!(unescape(&escape(s)), s);
+        }
+        assert_eq!(escape("a,b;c\\d\r\ne"), r"a\,b\;c\\d\ne");
+        assert_eq!(unescape(r"a\Nb\:c"), "a\nb:c");
+    }
+
+    #[test]
+    fn structured_values_respect_escaped_separators() {
+        let value = r"Smith\;Jones;Mary,Jane;;;";
+
+        assert_eq!(structured(value, 0, " "), "Smith;Jones");
+        assert_eq!(structured(value, 1, " "), "Mary Jane");
+        assert_eq!(structured(value, 2, " "), "");
+        assert_eq!(structured(value, 9, " "), "");
+        assert_eq!(split_escaped(r"a\,b,c", ','), [r"a\,b", "c"]);
+    }
+
+    #[test]
+    fn parameter_values_are_quoted_when_they_hold_separators() {
+        assert_eq!(parameter_value("boat"), "boat");
+        assert_eq!(parameter_value("boat; \"shed\""), "\"boat; shed\"");
+        assert_eq!(
+            split_unquoted("TEL;TYPE=\"a;b\":1", ';'),
+            ["TEL", "TYPE=\"a;b\":1"]
+        );
+    }
+
+    #[test]
+    fn contacts_round_trip() {
+        let original = contact();
+
+        for version in [VCardVersion::V3, VCardVersion::V4] {
+            assert_eq!(round_trip(&original, version), original);
+        }
+    }
+
+    #[test]
+    fn cards_that_cant_be_read_are_reported_with_their_line() {
+        let input = "BEGIN:VCARD\nVERSION:3.0\nTEL:2025550101\nEND:VCARD\n\
+                     BEGIN:VCARD\nFN:Jo Smith\nTEL;TYPE=CELL:2025550102\nEND:VCARD\n";
+        let cards = read(input, Region::default_region()).unwrap();
+
+        assert!(matches!(
+            cards[0].contact,
+            Err(PhoneBookError::InvalidVCard { line: 1, .. })
+        ));
+        let contact = cards[1].contact.as_ref().unwrap();
+        assert_eq!(
+            (contact.first_name.as_str(), contact.last_name.as_str()),
+            ("Jo", "Smith")
+        );
+        assert_eq!(cards[1].line, 5);
+
+        assert!(matches!(
+            read("FN:Jo\n", Region::default_region()),
+            Err(PhoneBookError::InvalidVCard { line: 1, .. })
+        ));
+    }
+}